@@ -0,0 +1,56 @@
+// Plain stdout output for the headless runners. Drawing the board needs curses and lives in
+// tui.rs instead.
+use std::time;
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use solitaire::session::Session;
+
+pub fn print_stats(session: &Session) {
+    let elapsed = Dur { dur: session.started.elapsed() };
+    print!("{} | {} g/s, {} w/s ",
+                          elapsed,
+                          session.games / (elapsed.dur.as_secs() as usize + 1),
+                          session.wins  / (elapsed.dur.as_secs() as usize + 1));
+    print!("Score: {} | Winrate: {:4.2}% ({}/{}) | Moves: {}       \r", 
+                          session.score, 
+                          100.0 *(session.wins as f32 / session.games as f32),
+                          session.wins,
+                          session.games,
+                          session.moves);
+    io::stdout().flush().unwrap();
+
+}
+
+// Final line for a finished run, the stdout counterpart of tui::print_stats_curses
+#[cfg(not(feature = "tui"))]
+pub fn print_summary(session: &Session, ended: time::Instant) {
+    let elapsed = Dur { dur: ended.duration_since(session.started) };
+    print!("{} | {} g/s, {} w/s ",
+                          elapsed,
+                          session.games / (elapsed.dur.as_secs() as usize + 1),
+                          session.wins  / (elapsed.dur.as_secs() as usize + 1));
+    println!("Score: {} | Winrate: {:4.2}% ({}/{}) | Moves: {}", 
+                          session.score, 
+                          100.0 *(session.wins as f32 / session.games as f32),
+                          session.wins,
+                          session.games,
+                          session.moves);
+}
+
+pub struct Dur {
+    pub dur: time::Duration,
+}
+impl fmt::Display for Dur {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut secs = self.dur.as_secs();
+
+        let hrs = secs/3600;
+        secs -= hrs * 3600;
+
+        let mins = secs/60;
+        secs -= mins * 60;
+        write!(f, "{:02}:{:02}:{:02}", hrs, mins, secs)
+    }
+}
@@ -1,64 +1,341 @@
+//! The rules of Klondike: cards, dealing, moves and undo. Nothing in here prints, so a Game can
+//! be driven by any front-end or run headless.
 extern crate rand;
 use self::rand::Rng;
-use std::clone::Clone;
 use std::time;
 use std::fmt;
-use std::io;
-use std::io::Write;
+use std::error;
+use std::str::FromStr;
 
-use super::ncurses as term;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
-use super::PAIR_RED;
-use super::PAIR_BLK;
+use scoring::{Scoring, VegasCumulative};
+use zobrist::{position_hash, move_hash};
 
-#[derive(Debug)]
+/// One deal of Klondike. Create with game_init and deal with game_restart or deal
+///
+/// With the serde feature a Game serialises as the position only: the fields below under their
+/// own names, cards as `{"suit": "Hearts", "rank": "Ace", "up": true}` and moves tagged with the
+/// Move variant name. The scoring scheme is stored by its name and the deal time as
+/// `elapsed_secs`, the seconds played so far, so a loaded game's clock carries on from there.
+/// The hash is left out and worked out again as the game is read back.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "serde_fields::Loaded"))]
 pub struct Game {
+    /// The tableau, the last card of each pile is the one that can be played
     pub piles: [Vec<Card>; 7],
+    /// The stock, drawn from the end
     pub side_deck: Vec<Card>,
+    /// The waste, its last card is the one that can be played
     pub hand: Vec<Card>,
     pub foundations: [Vec<Card>; 4],
+    /// Score for this deal from the moves made, see timed_score for the time taken as well
     pub score: isize,
-    pub games: usize,
-    pub wins: usize,
+    /// Moves made this deal
     pub moves: usize,
-    pub started: time::Instant,
+    /// Moves played this deal, most recent last
+    pub history: Vec<Step>,
+    /// Moves taken back by undo, next to redo last
+    pub undone: Vec<Move>,
+    /// Deal number of the current layout, None if dealt from a given deck
+    pub seed: Option<u64>,
+    pub rules: Rules,
+    /// Times the hand has been turned back over this deal
+    pub recycles: usize,
+    /// Whether make_move follows each move with auto_play_safe. Kept across deals
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub auto_play: bool,
+    /// Zobrist hash of the position, kept up to date by make_move and undo. See zobrist. After
+    /// changing the cards directly it is stale until zobrist::rehash is called
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub hash: u64,
+    #[cfg_attr(feature = "serde", serde(with = "serde_fields::scoring"))]
+    pub scoring: &'static dyn Scoring,
+    /// When the current layout was dealt
+    #[cfg_attr(feature = "serde", serde(rename = "elapsed_secs", with = "serde_fields::elapsed"))]
+    pub dealt: time::Instant,
 }
 
-#[derive(Debug, Clone)]
+/// The variant of Klondike being played. Kept across deals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rules {
+    /// Cards turned from the side deck per draw, usually 1 or 3. At 0 every draw is refused
+    pub draw_count: usize,
+    /// Times through the side deck allowed, None for unlimited. The first pass cannot be taken
+    /// away, so Some(0) plays the same as Some(1) and replays refuse it
+    pub max_passes: Option<usize>,
+    pub empty_column: EmptyColumn,
+    /// Thoughtful Solitaire: every card may be looked at, face down or not, though the moves
+    /// are the same. See Game::known
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub open: bool,
+}
+
+/// Which cards may be moved into an empty pile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EmptyColumn {
+    KingsOnly,
+    AnyCard,
+}
+
+/// Draw three, unlimited passes, kings only to empty piles, face down cards hidden
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            draw_count: 3,
+            max_passes: None,
+            empty_column: EmptyColumn::KingsOnly,
+            open: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Card {
-    pub suit: char,
-    pub number: u8,
+    pub suit: Suit,
+    pub rank: Rank,
+    /// Whether the card is face up
     pub up: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Suit {
+    Hearts,
+    Spades,
+    Diamonds,
+    Clubs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Colour {
+    Red,
+    Black,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Rank {
+    Ace = 1,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Ten,
+    Jack,
+    Queen,
+    King,
+}
+
+/// Returned when a string does not name a card, suit or rank
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCardError(pub String);
+
+impl fmt::Display for ParseCardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Not a card: {:?}", self.0)
+    }
+}
+
+impl error::Error for ParseCardError {}
+
+impl Suit {
+    pub const ALL: [Suit; 4] = [Suit::Hearts, Suit::Spades, Suit::Diamonds, Suit::Clubs];
+
+    pub fn colour(self) -> Colour {
+        match self {
+            Suit::Hearts | Suit::Diamonds => Colour::Red,
+            Suit::Spades | Suit::Clubs => Colour::Black,
+        }
+    }
+
+    pub fn letter(self) -> char {
+        match self {
+            Suit::Hearts => 'H',
+            Suit::Spades => 'S',
+            Suit::Diamonds => 'D',
+            Suit::Clubs => 'C',
+        }
+    }
+
+    pub fn from_letter(letter: char) -> Option<Suit> {
+        match letter.to_ascii_uppercase() {
+            'H' => Some(Suit::Hearts),
+            'S' => Some(Suit::Spades),
+            'D' => Some(Suit::Diamonds),
+            'C' => Some(Suit::Clubs),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Suit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.letter())
+    }
+}
+
+impl FromStr for Suit {
+    type Err = ParseCardError;
+
+    fn from_str(s: &str) -> Result<Suit, ParseCardError> {
+        let mut chars = s.chars();
+        match (chars.next().and_then(Suit::from_letter), chars.next()) {
+            (Some(suit), None) => Ok(suit),
+            _ => Err(ParseCardError(s.to_string())),
+        }
+    }
+}
+
+impl Rank {
+    pub const ALL: [Rank; 13] = [Rank::Ace, Rank::Two, Rank::Three, Rank::Four, Rank::Five,
+                                 Rank::Six, Rank::Seven, Rank::Eight, Rank::Nine, Rank::Ten,
+                                 Rank::Jack, Rank::Queen, Rank::King];
+
+    /// 1 for an Ace up to 13 for a King
+    pub fn value(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_value(value: u8) -> Option<Rank> {
+        if (1..=13).contains(&value) {
+            Some(Rank::ALL[value as usize - 1])
+        } else {
+            None
+        }
+    }
+
+    /// The rank one higher, None for a King
+    pub fn next(self) -> Option<Rank> {
+        Rank::from_value(self.value() + 1)
+    }
+
+    /// The rank one lower, None for an Ace
+    pub fn prev(self) -> Option<Rank> {
+        Rank::from_value(self.value() - 1)
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rank::Ace => f.pad("A"),
+            Rank::Jack => f.pad("J"),
+            Rank::Queen => f.pad("Q"),
+            Rank::King => f.pad("K"),
+            r => f.pad(&r.value().to_string()),
+        }
+    }
+}
+
+/// Only the Display forms are read, in either case: "A", "2" to "10", "J", "Q" and "K"
+impl FromStr for Rank {
+    type Err = ParseCardError;
+
+    fn from_str(s: &str) -> Result<Rank, ParseCardError> {
+        let upper = s.to_ascii_uppercase();
+        Rank::ALL.iter().cloned().find(|rank| rank.to_string() == upper)
+            .ok_or_else(|| ParseCardError(s.to_string()))
+    }
+}
+
+impl Card {
+    pub fn colour(&self) -> Colour {
+        self.suit.colour()
+    }
+
+    /// 0 - 51, numbering the cards in deck() order
+    pub fn index(&self) -> usize {
+        self.suit as usize * 13 + self.rank.value() as usize - 1
+    }
+}
+
+/// Written as rank then suit, "10H" or "AS". Padding applies to the card as a whole
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&format!("{}{}", self.rank, self.suit))
+    }
+}
+
+/// Parses the Display form back into a face down card. Letters may be either case, as in move
+/// notation, and space around the card is ignored
+impl FromStr for Card {
+    type Err = ParseCardError;
+
+    fn from_str(s: &str) -> Result<Card, ParseCardError> {
+        let err = || ParseCardError(s.to_string());
+        let s = s.trim();
+        let split = s.char_indices().last().ok_or_else(err)?.0;
+        let rank = s[..split].parse().map_err(|_| err())?;
+        let suit = s[split..].parse().map_err(|_| err())?;
+        Ok(card(suit, rank))
+    }
+}
+
+/// A game with the default rules and nothing dealt yet
 pub fn game_init() -> Game {
+    game_init_with_rules(Rules::default())
+}
+
+pub fn game_init_with_rules(rules: Rules) -> Game {
     Game {
         piles: [vec![],vec![],vec![],vec![], vec![], vec![], vec![]],
         side_deck: vec![],
         hand: vec![],
         foundations: [vec![], vec![], vec![], vec![]],
         score: 0,
-        games: 0,
-        wins: 0,
         moves: 0,
-        started: time::Instant::now(),
+        history: vec![],
+        undone: vec![],
+        seed: None,
+        rules,
+        recycles: 0,
+        auto_play: false,
+        hash: 0,
+        scoring: default_scoring(),
+        dealt: time::Instant::now(),
     }
 }
 
-// Creates a new shuffled deck and begins the game
+// The scheme game_init starts with, also given to deserialised games that do not name one
+fn default_scoring() -> &'static dyn Scoring {
+    &VegasCumulative
+}
+
+/// Creates a new shuffled deck and begins the game
 pub fn game_restart(game: &mut Game) {
-    let deck = deck();
-    let mut deck = shuffle(&deck);
+    let seed = rand::thread_rng().gen();
+    game_restart_with_seed(game, seed);
+}
+
+/// Begins the game with the given deal number. A seed always produces the same layout
+pub fn game_restart_with_seed(game: &mut Game, seed: u64) {
+    let mut deck = shuffle_seeded(seed);
     deal(game, &mut deck);
-    game.score -= 52;
-    game.games += 1;
+    game.seed = Some(seed);
 }
 
-// Deals the given deck into the given game. The deck given should ALREADY BE SHUFFLED
+/// Deals the given deck into the given game. The deck given should ALREADY BE SHUFFLED
 pub fn deal(game: &mut Game, deck: &mut Vec<Card>) {
     game.piles = [vec![],vec![],vec![],vec![], vec![], vec![], vec![]];
     game.foundations = [vec![], vec![], vec![], vec![]];
     game.hand = vec![];
+    game.history = vec![];
+    game.undone = vec![];
+    game.seed = None;
+    game.recycles = 0;
+    game.dealt = time::Instant::now();
+    game.moves = 0;
+    game.score = game.scoring.deal();
 
     // Populate the piles
     for (i, pile) in game.piles.iter_mut().enumerate() {
@@ -69,420 +346,1523 @@ pub fn deal(game: &mut Game, deck: &mut Vec<Card>) {
     }
 
     game.side_deck = deck.to_vec();
+    game.hash = position_hash(game);
 }
 
-pub fn print_stats(game: &Game) {
-    let elapsed = Dur { dur: game.started.elapsed() };
-    print!("{} | {} g/s, {} w/s ",
-                          elapsed,
-                          game.games / (elapsed.dur.as_secs() as usize + 1),
-                          game.wins  / (elapsed.dur.as_secs() as usize + 1));
-    print!("Score: {} | Winrate: {:4.2}% ({}/{}) | Moves: {}       \r", 
-                          game.score, 
-                          100.0 *(game.wins as f32 / game.games as f32),
-                          game.wins,
-                          game.games,
-                          game.moves);
-    io::stdout().flush();
-
-}
-
-pub fn print_stats_curses(game: &Game, ended: time::Instant) {
-    let elapsed = Dur { dur: ended.duration_since(game.started) };
-    term::printw(&format!("{} | {} g/s, {} w/s ",
-                          elapsed,
-                          game.games / (elapsed.dur.as_secs() as usize + 1),
-                          game.wins  / (elapsed.dur.as_secs() as usize + 1)));
-    term::printw(&format!("Score: {} | Winrate: {:4.2}% ({}/{}) | Moves: {}      \n\r", 
-                          game.score, 
-                          100.0 *(game.wins as f32 / game.games as f32),
-                          game.wins,
-                          game.games,
-                          game.moves));
-}
-
-
-pub fn print_game(game: &Game) {
-    term::clear();
-    let elapsed = Dur { dur: game.started.elapsed() };
-    term::printw(&format!("{} | {} g/s, {} w/s ",
-                          elapsed,
-                          game.games / (elapsed.dur.as_secs() as usize + 1),
-                          game.wins  / (elapsed.dur.as_secs() as usize + 1)));
-    term::printw(&format!("Score: {} | Winrate: {:4.2}% ({}/{}) | Moves: {}\n", 
-                          game.score, 
-                          100.0 *(game.wins as f32 / game.games as f32),
-                          game.wins,
-                          game.games,
-                          game.moves));
-    // Top line of game in parts:
-    // 1. Side deck size
-    term::printw(&format!("({:2})[", game.side_deck.len()));
-    // 2. Hand
-    match game.hand.last() {
-        None => (),
-        Some(c) => set_colour(c),
-    }
-    term::printw(&format!("{}", 
-                          match game.hand.last() {
-                              None => "   ".to_string(),
-                              Some(c) => card_string(c),
-                          }));
-    match game.hand.last() {
-        None => (),
-        Some(c) => clear_colour(c),
-    }
-    term::printw("]  ");
-
-    // 3. Foundations
+/// Whether every card is on the foundations
+pub fn game_won(game: &Game) -> bool {
     for found in &game.foundations {
-        term::printw("[");
         match found.last() {
-            None => (),
-            Some(c) => set_colour(c),
-        }
-        term::printw(&format!("{}",
-                              match found.last() {
-                                  None => "   ".to_string(),
-                                  Some(c) => card_string(c),
-                              }));
-        match found.last() {
-            None => (),
-            Some(c) => clear_colour(c),
-        }
-        term::printw("]");
-    }
-    term::printw("\n");
-    term::printw("=============================\n");
-    let mut cards = true;
-    let mut row = 0;
-    while cards {
-        cards = false;
-        for pile in &game.piles {
-            if row < pile.len() {
-                cards = true;
-                set_colour(&pile[row]);
-                term::printw(&format!("{} ", card_str_disp(&pile[row])));
-                clear_colour(&pile[row]);
-            } else {
-                term::printw("    ");
+            None => {return false},
+            Some(c) => { if c.rank != Rank::King {return false}}
+        }
+    }
+    true
+}
+
+/// Where a deal stands, see game_status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum GameStatus {
+    InProgress,
+    Won,
+    /// No sequence of draws, recycles and moves can get the deal any further
+    Lost,
+}
+
+/// Whether the deal is won, lost or still going. A deal is lost once a whole turn through the
+/// side deck, as far as the passes left allow, would find no productive move with any card on top
+/// of the hand. Moves that only swap cards between equivalent places are not productive, as they
+/// could be played back and forth forever without changing what can be done
+pub fn game_status(game: &Game) -> GameStatus {
+    if game_won(game) {
+        return GameStatus::Won;
+    }
+    if game.legal_moves().iter().any(|&mv| productive(game, mv, true)) {
+        return GameStatus::InProgress;
+    }
+
+    // Drawing only changes the top of the hand, so from here on only the moves it could take
+    // part in need trying again
+    let hand_moves = (0..7).map(|dest| Move::WasteToTableau { dest })
+        .chain((0..4).map(|dest| Move::WasteToFoundation { dest }))
+        .chain((0..4).flat_map(|src| (0..7).map(move |dest| Move::FoundationToTableau { src, dest })));
+    let mut probe = Game {
+        piles: game.piles.clone(),
+        side_deck: game.side_deck.clone(),
+        hand: game.hand.clone(),
+        foundations: game.foundations.clone(),
+        history: vec![],
+        undone: vec![],
+        ..*game
+    };
+    // The side deck runs out once on the way round from wherever it is now, and again after a
+    // full turn from the top. Every later turn is the same as that one
+    let mut run_out = 0;
+    loop {
+        if probe.side_deck.is_empty() {
+            run_out += 1;
+            if run_out == 2 || apply_move(&mut probe, Move::Recycle).is_err() {
+                return GameStatus::Lost;
             }
+        } else if apply_move(&mut probe, Move::Draw).is_err() {
+            return GameStatus::Lost;
+        }
+        if hand_moves.clone().any(|mv| check_move(&probe, mv).is_ok() && productive(&probe, mv, true)) {
+            return GameStatus::InProgress;
         }
-        row += 1;
-        term::printw("\n");
     }
-    term::refresh();
 }
 
-pub fn game_won(game: &mut Game) -> bool {
-    for found in &game.foundations {
-        match found.last() {
-            None => {return false},
-            Some(c) => { if c.number != 13 {return false}}
+// Whether a legal move gets the deal anywhere. Between piles that means turning over a card,
+// emptying a pile or freeing a card for the foundations; moving a card off a foundation means
+// letting something productive be played onto it, looked for only when look_ahead is set. What
+// is played onto it may itself come off a foundation, so chains of them are followed down, each
+// a rank lower than the last
+fn productive(game: &Game, mv: Move, look_ahead: bool) -> bool {
+    match mv {
+        Move::Draw | Move::Recycle => false,
+        Move::WasteToTableau { .. } | Move::WasteToFoundation { .. } | Move::TableauToFoundation { .. } => true,
+        Move::TableauToTableau { src, depth, dest } => {
+            let pile = &game.piles[src];
+            match pile.len().checked_sub(depth + 1).map(|i| pile[i]) {
+                None => !game.piles[dest].is_empty(),
+                Some(below) => !below.up || game.foundation_for(below.suit)
+                    .is_some_and(|f| check_found_target(game, &below, f).is_ok()),
+            }
+        },
+        Move::FoundationToTableau { dest, .. } => look_ahead && {
+            let mut next = game.clone();
+            apply_move(&mut next, mv).expect("Playing a legal move");
+            next.legal_moves().iter().any(|&next_mv| match next_mv {
+                Move::WasteToTableau { dest: to } | Move::TableauToTableau { dest: to, .. } => {
+                    to == dest && productive(&next, next_mv, false)
+                },
+                Move::FoundationToTableau { dest: to, .. } => to == dest && productive(&next, next_mv, true),
+                _ => false,
+            })
+        },
+    }
+}
+
+/// How a deal ended, for a Session to keep count of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameResult {
+    pub won: bool,
+    /// Final score for the deal, including the time taken and any bonus for winning
+    pub score: isize,
+    pub moves: usize,
+    /// Whether the scoring carries this score on into the next deal
+    pub cumulative: bool,
+}
+
+/// The score for the deal so far with the scheme's charge for the time taken, held to its floor
+pub fn timed_score(game: &Game) -> isize {
+    let score = game.score + game.scoring.on_time(game.dealt.elapsed());
+    match game.scoring.floor() {
+        Some(floor) => score.max(floor),
+        None => score,
+    }
+}
+
+/// Sums up the deal as it stands. Call it before dealing again
+pub fn game_result(game: &Game) -> GameResult {
+    let won = game_won(game);
+    let bonus = if won { game.scoring.on_win(game.dealt.elapsed()) } else { 0 };
+    GameResult {
+        won,
+        score: timed_score(game) + bonus,
+        moves: game.moves,
+        cumulative: game.scoring.cumulative(),
+    }
+}
+
+/// A single action on the board. Tableau piles are numbered 0 - 6 and foundations 0 - 3. A
+/// foundation takes any ace while empty and that suit from then on, see Game::foundation_for.
+/// The hand is the waste, the side deck is the stock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Move {
+    /// Turn up to Rules::draw_count cards from the side deck onto the hand
+    Draw,
+    /// Turn the hand back over to form the side deck again
+    Recycle,
+    WasteToTableau { dest: usize },
+    WasteToFoundation { dest: usize },
+    /// depth is the number of cards to take from the bottom of the source pile
+    TableauToTableau { src: usize, depth: usize, dest: usize },
+    TableauToFoundation { src: usize, dest: usize },
+    /// Take the top card of a foundation back down onto a pile
+    FoundationToTableau { src: usize, dest: usize },
+}
+
+/// What a successful move did to the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MoveOutcome {
+    /// Number of cards moved, drawn or recycled
+    pub cards: usize,
+    /// Whether a face down card was turned up in the source pile
+    pub revealed: bool,
+    /// Change in score caused by the move
+    pub score: isize,
+}
+
+/// A move that has been played, with enough detail to take it back again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Step {
+    pub mv: Move,
+    pub outcome: MoveOutcome,
+}
+
+/// Why a move was rejected. The board is left untouched whenever one of these is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    NoSuchPile(usize),
+    NoSuchFoundation(usize),
+    NoCardInHand,
+    TooManyFromHand,
+    NotEnoughCards { available: usize, requested: usize },
+    ZeroDepth,
+    SamePile,
+    /// Some of the cards to be moved between piles are face down
+    FaceDownInRun,
+    /// The cards to be moved between piles do not alternate colour and descend
+    BrokenRun,
+    TooManyToFoundation,
+    TooManyFromFoundation,
+    EmptyFoundation,
+    SuitMismatch,
+    NotAscending,
+    SuitsMustAlternate,
+    NotDescending,
+    NonKingToEmpty,
+    KingToNonEmpty,
+    StockEmpty,
+    /// The rules draw no cards at a time
+    NoCardsDrawn,
+    StockNotEmpty,
+    NothingToRecycle,
+    NoPassesLeft,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MoveError::NoSuchPile(i) => write!(f, "No pile {}", i),
+            MoveError::NoSuchFoundation(i) => write!(f, "No foundation {}", i),
+            MoveError::NoCardInHand => write!(f, "No cards in hand"),
+            MoveError::TooManyFromHand => write!(f, "Cannot take more than one card from hand"),
+            MoveError::NotEnoughCards { available, requested } =>
+                write!(f, "Trying to take {} cards from a pile of {}", requested, available),
+            MoveError::ZeroDepth => write!(f, "Must take at least one card"),
+            MoveError::SamePile => write!(f, "Source and destination piles are the same"),
+            MoveError::FaceDownInRun => write!(f, "Cannot move face down cards"),
+            MoveError::BrokenRun => write!(f, "Cards moved together must alternate colours and descend"),
+            MoveError::TooManyToFoundation => write!(f, "Cannot move more than one card to foundation"),
+            MoveError::TooManyFromFoundation => write!(f, "Cannot take more than one card from foundation"),
+            MoveError::EmptyFoundation => write!(f, "No card on the foundation to take"),
+            MoveError::SuitMismatch => write!(f, "Suits must match on the foundations"),
+            MoveError::NotAscending => write!(f, "Numbers must ascend by one on the foundations"),
+            MoveError::SuitsMustAlternate => write!(f, "Suit colours must alternate on the piles"),
+            MoveError::NotDescending => write!(f, "Numbers must decrease by one on the piles"),
+            MoveError::NonKingToEmpty => write!(f, "Only Kings can move to empty piles"),
+            MoveError::KingToNonEmpty => write!(f, "Kings can only be moved to empty piles"),
+            MoveError::StockEmpty => write!(f, "No cards left in the side deck"),
+            MoveError::NoCardsDrawn => write!(f, "The rules draw no cards from the side deck"),
+            MoveError::StockNotEmpty => write!(f, "Cannot recycle while the side deck has cards"),
+            MoveError::NothingToRecycle => write!(f, "No cards in hand to recycle"),
+            MoveError::NoPassesLeft => write!(f, "No more passes through the side deck allowed"),
         }
     }
-    game.wins += 1;
-    game.score += 5000;
-    true
 }
 
-// Make a move. pile indices start at zero, naturally, with piles 7 - 10 representing the
-// foundations (Hearts, Spades, Diamonds, Clubs)
-// src_depth refers to the number of cards to take from the source pile.
-pub fn make_move(game: &mut Game, src_pile: usize, src_depth: usize, dest_pile: usize) -> bool {
+impl error::Error for MoveError {}
+
+/// Returned when a string is not a move in the notation described at Move's Display
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMoveError(pub String);
+
+impl fmt::Display for ParseMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Not a move: {:?}", self.0)
+    }
+}
+
+impl error::Error for ParseMoveError {}
+
+/// Something that can never be true of a board the rules produced, found by Game::validate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// The board holds this many cards rather than 52
+    CardCount(usize),
+    DuplicateCard(Card),
+    /// A face down card lies on top of a face up one in this pile
+    FaceDownOnFaceUp { pile: usize },
+    TopCardFaceDown { pile: usize },
+    /// The card at this index does not alternate colour and descend from the one below it
+    BrokenRun { pile: usize, index: usize },
+    /// The card at this index is not the next of the foundation's suit
+    FoundationOutOfOrder { foundation: usize, index: usize },
+    FaceDownOnFoundation { foundation: usize },
+    FaceDownInHand,
+    FaceUpInSideDeck,
+    /// Game::hash does not match the cards
+    StaleHash,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvariantViolation::CardCount(n) => write!(f, "There are {} cards instead of 52", n),
+            InvariantViolation::DuplicateCard(c) => write!(f, "{} is in the game twice", c),
+            InvariantViolation::FaceDownOnFaceUp { pile } =>
+                write!(f, "Face down card on a face up one in pile {}", pile + 1),
+            InvariantViolation::TopCardFaceDown { pile } => write!(f, "Top card of pile {} is face down", pile + 1),
+            InvariantViolation::BrokenRun { pile, index } =>
+                write!(f, "Card {} of pile {} breaks the run", index + 1, pile + 1),
+            InvariantViolation::FoundationOutOfOrder { foundation, index } =>
+                write!(f, "Card {} of foundation {} is out of order", index + 1, foundation + 1),
+            InvariantViolation::FaceDownOnFoundation { foundation } =>
+                write!(f, "Face down card on foundation {}", foundation + 1),
+            InvariantViolation::FaceDownInHand => write!(f, "Face down card in the hand"),
+            InvariantViolation::FaceUpInSideDeck => write!(f, "Face up card in the side deck"),
+            InvariantViolation::StaleHash => write!(f, "The hash does not match the position"),
+        }
+    }
+}
+
+impl error::Error for InvariantViolation {}
+
+/// Written as source>destination. W is the hand, 1 - 7 the tableau piles and F1 - F4 the
+/// foundations, with xN after the destination when N > 1 cards move between piles. D draws and R
+/// recycles. For example "W>F1", "3>5x2", "F2>7", "D"
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Move::Draw => write!(f, "D"),
+            Move::Recycle => write!(f, "R"),
+            Move::WasteToTableau { dest } => write!(f, "W>{}", dest + 1),
+            Move::WasteToFoundation { dest } => write!(f, "W>F{}", dest + 1),
+            Move::TableauToTableau { src, depth: 1, dest } => write!(f, "{}>{}", src + 1, dest + 1),
+            Move::TableauToTableau { src, depth, dest } => write!(f, "{}>{}x{}", src + 1, dest + 1, depth),
+            Move::TableauToFoundation { src, dest } => write!(f, "{}>F{}", src + 1, dest + 1),
+            Move::FoundationToTableau { src, dest } => write!(f, "F{}>{}", src + 1, dest + 1),
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = ParseMoveError;
+
+    fn from_str(s: &str) -> Result<Move, ParseMoveError> {
+        parse_move_with(s, |_| None)
+    }
+}
+
+impl Move {
+    /// Reads a move in the Display notation, ignoring case and surrounding space
+    pub fn parse(s: &str) -> Result<Move, ParseMoveError> {
+        s.parse()
+    }
+}
+
+// One end of a move in the notation
+enum Place {
+    Hand,
+    Pile(usize),
+    Foundation(usize),
+}
+
+// Parses the move notation. Foundations may also be named by suit letter where found_for can say
+// which foundation that is
+fn parse_move_with<F: Fn(Suit) -> Option<usize>>(s: &str, found_for: F) -> Result<Move, ParseMoveError> {
+    let err = || ParseMoveError(s.to_string());
+    // Plain digits only, as usize's own parsing would let a sign through
+    let number = |n: &str| -> Result<usize, ParseMoveError> {
+        if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(err());
+        }
+        n.parse().map_err(|_| err())
+    };
+    let text = s.trim().to_ascii_uppercase();
+    match text.as_str() {
+        "D" => return Ok(Move::Draw),
+        "R" => return Ok(Move::Recycle),
+        _ => (),
+    }
 
+    let place = |p: &str| -> Result<Place, ParseMoveError> {
+        let mut chars = p.chars();
+        match (chars.next(), chars.as_str()) {
+            (Some('W'), "") => Ok(Place::Hand),
+            (Some('F'), n) => match number(n) {
+                Ok(n) if (1..=4).contains(&n) => Ok(Place::Foundation(n - 1)),
+                _ => Err(err()),
+            },
+            (Some(c), "") if Suit::from_letter(c).is_some() => {
+                Suit::from_letter(c).and_then(&found_for).map(Place::Foundation).ok_or_else(err)
+            },
+            _ => match number(p) {
+                Ok(n) if (1..=7).contains(&n) => Ok(Place::Pile(n - 1)),
+                _ => Err(err()),
+            },
+        }
+    };
+
+    let (src, rest) = text.split_at(text.find('>').ok_or_else(err)?);
+    let rest = &rest[1..];
+    let (dest, depth) = match rest.find('X') {
+        None => (rest, None),
+        Some(i) => match number(&rest[i + 1..])? {
+            0 => return Err(err()),
+            depth => (&rest[..i], Some(depth)),
+        },
+    };
+
+    Ok(match (place(src)?, place(dest)?, depth) {
+        (Place::Hand, Place::Pile(dest), None) => Move::WasteToTableau { dest },
+        (Place::Hand, Place::Foundation(dest), None) => Move::WasteToFoundation { dest },
+        (Place::Pile(src), Place::Pile(dest), depth) => {
+            Move::TableauToTableau { src, depth: depth.unwrap_or(1), dest }
+        },
+        (Place::Pile(src), Place::Foundation(dest), None) => Move::TableauToFoundation { src, dest },
+        (Place::Foundation(src), Place::Pile(dest), None) => Move::FoundationToTableau { src, dest },
+        _ => return Err(err()),
+    })
+}
+
+/// Make a move, leaving the game untouched if it is not legal. A new move discards anything that
+/// could have been redone. With Game::auto_play on, any cards the move makes safe go up to the
+/// foundations as moves of their own, to be undone one at a time
+pub fn make_move(game: &mut Game, mv: Move) -> Result<MoveOutcome, MoveError> {
+    let outcome = apply_move(game, mv)?;
+    game.undone.clear();
+    if game.auto_play {
+        auto_play_safe(game);
+    }
+    Ok(outcome)
+}
+
+/// Moves to the foundations, one at a time, every card from the hand or the bottom of a pile that
+/// is safe to put there, see safe_to_foundation. Returns the number of cards moved
+pub fn auto_play_safe(game: &mut Game) -> usize {
+    let mut played = 0;
+    while let Some(mv) = safe_foundation_move(game) {
+        apply_move(game, mv).expect("Playing a safe card to the foundations");
+        played += 1;
+    }
+    if played > 0 {
+        game.undone.clear();
+    }
+    played
+}
+
+/// Whether putting the card on the foundations can never cost the game: it is an Ace or a Two, or
+/// both cards of the other colour one rank below are already up, so nothing left on the tableau
+/// could need it to build on
+pub fn safe_to_foundation(game: &Game, card: &Card) -> bool {
+    let needed = card.rank.value() as usize - 1;
+    needed <= 1 || Suit::ALL.iter()
+        .filter(|suit| suit.colour() != card.colour())
+        .all(|&suit| game.foundations.iter().any(|f| f.first().map(|c| c.suit) == Some(suit) && f.len() >= needed))
+}
+
+/// Whether the deal is as good as won: the side deck and hand are empty and every card left on
+/// the piles is face up, so the cards can always be played up to the foundations in rank order
+pub fn can_autocomplete(game: &Game) -> bool {
+    game.side_deck.is_empty() && game.hand.is_empty()
+        && game.piles.iter().all(|pile| pile.iter().all(|c| c.up))
+        && !game_won(game)
+}
+
+/// Finishes a deal that can_autocomplete, moving every card to the foundations one at a time.
+/// Returns the number of cards moved, 0 if the deal is not yet as good as won
+pub fn autocomplete(game: &mut Game) -> usize {
+    if !can_autocomplete(game) {
+        return 0;
+    }
+    let mut played = 0;
+    while !game_won(game) {
+        // The lowest card left is always at the bottom of a pile with its foundation ready for it
+        let (src, card) = game.piles.iter().enumerate()
+            .filter_map(|(src, pile)| pile.last().map(|card| (src, *card)))
+            .min_by_key(|&(_, card)| card.rank)
+            .expect("Cards left on the piles");
+        let dest = game.foundation_for(card.suit).expect("A foundation for every suit");
+        apply_move(game, Move::TableauToFoundation { src, dest }).expect("Playing the lowest card up");
+        played += 1;
+    }
+    game.undone.clear();
+    played
+}
+
+// The first legal move of a safe card to the foundations, hand first then the piles in order
+fn safe_foundation_move(game: &Game) -> Option<Move> {
+    let hand = game.hand.last().and_then(|card| {
+        let dest = game.foundation_for(card.suit)?;
+        Some((*card, Move::WasteToFoundation { dest }))
+    });
+    let piles = game.piles.iter().enumerate().filter_map(|(src, pile)| {
+        let card = pile.last()?;
+        let dest = game.foundation_for(card.suit)?;
+        Some((*card, Move::TableauToFoundation { src, dest }))
+    });
+    hand.into_iter().chain(piles)
+        .find(|&(card, mv)| safe_to_foundation(game, &card) && check_move(game, mv).is_ok())
+        .map(|(_, mv)| mv)
+}
+
+// Plays the move and records it in the history
+fn apply_move(game: &mut Game, mv: Move) -> Result<MoveOutcome, MoveError> {
+    check_move(game, mv)?;
+
+    let fresh = hash_fresh(game);
+
+    let drawn = game.rules.draw_count.min(game.side_deck.len());
+    let before = move_hash(game, mv, drawn, false);
+    let mut outcome = match mv {
+        Move::Draw => move_draw(game),
+        Move::Recycle => move_recycle(game),
+        Move::WasteToTableau { dest } => move_hand_pile(game, dest),
+        Move::WasteToFoundation { dest } => move_hand_found(game, dest),
+        Move::TableauToTableau { src, depth, dest } => move_pile_pile(game, src, depth, dest),
+        Move::TableauToFoundation { src, dest } => move_pile_found(game, src, dest),
+        Move::FoundationToTableau { src, dest } => move_found_pile(game, src, dest),
+    };
+    game.hash ^= before ^ move_hash(game, mv, outcome.cards, true);
+
+    let mut score = game.score + game.scoring.on_move(&mv, &outcome, &game.rules);
+    if let Some(floor) = game.scoring.floor() {
+        score = score.max(floor);
+    }
+    outcome.score = score - game.score;
+    game.score = score;
     game.moves += 1; // Don't talk to me
-    
-    // If src pile is 11, we are taking from the draw hand:
-    //   - must be a card in the hand to take
-    //   - depth must be one
-    if src_pile == 11 {
-        if game.hand.is_empty() {
-            term::printw("Error: No cards in hand\n");
-            return false;
-        } else if src_depth != 1 {
-            term::printw("Error: Cannot take more than one card from hand\n");
-            return false;
-        }
-
-        // Determine target area
-        if dest_pile > 6 {
-            return move_hand_found(game, dest_pile);
-        } else {
-            return move_hand_pile(game, dest_pile);
+    game.history.push(Step { mv, outcome });
+    debug_validate(game, mv, fresh);
+    Ok(outcome)
+}
+
+// Debug builds check the whole board after every move and undo, so that a bug in the rules shows
+// up at the move that caused it rather than thousands of moves later. A hash is only held to
+// being right if it was right before, as the cards may have been changed without rehashing
+fn debug_validate(game: &Game, mv: Move, fresh: bool) {
+    if cfg!(debug_assertions) {
+        match game.validate() {
+            Ok(()) => (),
+            Err(InvariantViolation::StaleHash) if !fresh => (),
+            Err(e) => panic!("{} after {}", e, mv),
         }
-        
     }
-        
-        
-    // Make sure that there is a card where we want to take from
-    if game.piles[src_pile].len() < src_depth {
-        term::printw("Error: Trying to take non-existant card\n");
-        return false;
+}
+
+// Whether the hash matches the position, only worked out in debug builds for debug_validate
+fn hash_fresh(game: &Game) -> bool {
+    cfg!(debug_assertions) && game.hash == position_hash(game)
+}
+
+/// Checks whether a move could be made without changing anything. This is the only place the rules
+/// are enforced; make_move and legal_moves both defer to it.
+pub fn check_move(game: &Game, mv: Move) -> Result<(), MoveError> {
+    match mv {
+        Move::Draw => {
+            if game.side_deck.is_empty() {
+                return Err(MoveError::StockEmpty);
+            } else if game.rules.draw_count == 0 {
+                return Err(MoveError::NoCardsDrawn);
+            }
+        },
+        Move::Recycle => {
+            if !game.side_deck.is_empty() {
+                return Err(MoveError::StockNotEmpty);
+            } else if game.hand.is_empty() {
+                return Err(MoveError::NothingToRecycle);
+            } else if let Some(max) = game.rules.max_passes {
+                if game.recycles + 1 >= max {
+                    return Err(MoveError::NoPassesLeft);
+                }
+            }
+        },
+        Move::WasteToTableau { dest } => {
+            check_pile(dest)?;
+            check_pile_target(game, &hand_card(game)?, dest)?;
+        },
+        Move::WasteToFoundation { dest } => {
+            check_found(dest)?;
+            check_found_target(game, &hand_card(game)?, dest)?;
+        },
+        Move::TableauToTableau { src, depth, dest } => {
+            check_pile(src)?;
+            check_pile(dest)?;
+            check_depth(game, src, depth)?;
+            if src == dest {
+                return Err(MoveError::SamePile);
+            }
+            let run = &game.piles[src][game.piles[src].len() - depth..];
+            check_run(run)?;
+            check_pile_target(game, &run[0], dest)?;
+        },
+        Move::TableauToFoundation { src, dest } => {
+            check_pile(src)?;
+            check_found(dest)?;
+            check_depth(game, src, 1)?;
+            check_found_target(game, game.piles[src].last().unwrap(), dest)?;
+        },
+        Move::FoundationToTableau { src, dest } => {
+            check_found(src)?;
+            check_pile(dest)?;
+            match game.foundations[src].last() {
+                None => return Err(MoveError::EmptyFoundation),
+                Some(card) => check_pile_target(game, card, dest)?,
+            }
+        },
+    }
+    Ok(())
+}
+
+impl Game {
+    /// The foundation holding the given suit, or the first empty one if the suit has not been
+    /// started. None only if the foundations have been filled with something other than four suits
+    pub fn foundation_for(&self, suit: Suit) -> Option<usize> {
+        self.foundations.iter().position(|f| f.first().map(|c| c.suit) == Some(suit))
+            .or_else(|| self.foundations.iter().position(|f| f.is_empty()))
+    }
+
+    /// Whether a player may know which card this is: any face up card, and every card once the
+    /// rules make the deal open
+    pub fn known(&self, card: &Card) -> bool {
+        card.up || self.rules.open
     }
-    
-    if dest_pile > 6 {
-        move_pile_found(game, src_pile, src_depth, dest_pile)
+
+    /// Reads a move in the notation of Move's Display, also allowing foundations to be named by
+    /// suit, as in "W>H" or "H>3", for the foundation that suit is played to in this position
+    pub fn parse_move(&self, s: &str) -> Result<Move, ParseMoveError> {
+        parse_move_with(s, |suit| self.foundation_for(suit))
+    }
+
+    /// Checks that the board is one the rules could have produced: all 52 cards once each, the
+    /// side deck face down, the hand face up, each pile face down cards under an alternating
+    /// descending face up run, each foundation one suit counting up from the Ace, and the hash
+    /// up to date
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let mut seen = [false; 52];
+        let mut count = 0;
+        {
+            let all = self.piles.iter().chain(self.foundations.iter())
+                .chain(Some(&self.hand)).chain(Some(&self.side_deck));
+            for card in all.flat_map(|cards| cards.iter()) {
+                let index = card.index();
+                if seen[index] {
+                    return Err(InvariantViolation::DuplicateCard(*card));
+                }
+                seen[index] = true;
+                count += 1;
+            }
+        }
+        if count != 52 {
+            return Err(InvariantViolation::CardCount(count));
+        }
+
+        if self.side_deck.iter().any(|c| c.up) {
+            return Err(InvariantViolation::FaceUpInSideDeck);
+        }
+        if self.hand.iter().any(|c| !c.up) {
+            return Err(InvariantViolation::FaceDownInHand);
+        }
+
+        for (pile, cards) in self.piles.iter().enumerate() {
+            if cards.last().is_some_and(|c| !c.up) {
+                return Err(InvariantViolation::TopCardFaceDown { pile });
+            }
+            let hidden = cards.iter().take_while(|c| !c.up).count();
+            if cards[hidden..].iter().any(|c| !c.up) {
+                return Err(InvariantViolation::FaceDownOnFaceUp { pile });
+            }
+            for index in hidden + 1..cards.len() {
+                let (below, card) = (&cards[index - 1], &cards[index]);
+                if !suit_alternates(below, card) || !number_match_desc(Some(below), card) {
+                    return Err(InvariantViolation::BrokenRun { pile, index });
+                }
+            }
+        }
+
+        for (foundation, cards) in self.foundations.iter().enumerate() {
+            if cards.iter().any(|c| !c.up) {
+                return Err(InvariantViolation::FaceDownOnFoundation { foundation });
+            }
+            for (index, card) in cards.iter().enumerate() {
+                if card.suit != cards[0].suit || !number_match_asc(index, card) {
+                    return Err(InvariantViolation::FoundationOutOfOrder { foundation, index });
+                }
+            }
+        }
+
+        if self.hash != position_hash(self) {
+            return Err(InvariantViolation::StaleHash);
+        }
+        Ok(())
+    }
+
+    /// Every move that make_move would accept in the current position, draws and recycles included
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        let mut try_move = |mv| if check_move(self, mv).is_ok() { moves.push(mv) };
+
+        try_move(Move::Draw);
+        try_move(Move::Recycle);
+        for dest in 0..7 {
+            try_move(Move::WasteToTableau { dest });
+        }
+        for dest in 0..4 {
+            try_move(Move::WasteToFoundation { dest });
+        }
+        for (src, pile) in self.piles.iter().enumerate() {
+            // Only face up cards can be moved, so deeper runs need not be tried
+            let face_up = pile.iter().rev().take_while(|c| c.up).count();
+            for depth in 1..face_up + 1 {
+                for dest in 0..7 {
+                    try_move(Move::TableauToTableau { src, depth, dest });
+                }
+            }
+            for dest in 0..4 {
+                try_move(Move::TableauToFoundation { src, dest });
+            }
+        }
+        for src in 0..4 {
+            for dest in 0..7 {
+                try_move(Move::FoundationToTableau { src, dest });
+            }
+        }
+        moves
+    }
+}
+
+/// Takes back the last move played, returning it. None if there is nothing to undo
+pub fn undo(game: &mut Game) -> Option<Move> {
+    let step = game.history.pop()?;
+    let fresh = hash_fresh(game);
+    let before = move_hash(game, step.mv, step.outcome.cards, true);
+    match step.mv {
+        Move::Draw => {
+            for _ in 0..step.outcome.cards {
+                let card = game.hand.pop().unwrap();
+                game.side_deck.push(Card { up: false, ..card });
+            }
+        },
+        Move::Recycle => {
+            game.hand = game.side_deck.drain(..).rev().map(|c| reveal(&c)).collect();
+            game.recycles -= 1;
+        },
+        Move::WasteToTableau { dest } => {
+            let card = game.piles[dest].pop().unwrap();
+            game.hand.push(card);
+        },
+        Move::WasteToFoundation { dest } => {
+            let card = game.foundations[dest].pop().unwrap();
+            game.hand.push(card);
+        },
+        Move::TableauToTableau { src, depth, dest } => {
+            if step.outcome.revealed {
+                hide_last(&mut game.piles[src]);
+            }
+            let split_index = game.piles[dest].len() - depth;
+            let mut cards = game.piles[dest].split_off(split_index);
+            game.piles[src].append(&mut cards);
+        },
+        Move::TableauToFoundation { src, dest } => {
+            if step.outcome.revealed {
+                hide_last(&mut game.piles[src]);
+            }
+            let card = game.foundations[dest].pop().unwrap();
+            game.piles[src].push(card);
+        },
+        Move::FoundationToTableau { src, dest } => {
+            let card = game.piles[dest].pop().unwrap();
+            game.foundations[src].push(card);
+        },
+    }
+    game.hash ^= before ^ move_hash(game, step.mv, step.outcome.cards, false);
+    game.score -= step.outcome.score;
+    game.moves -= 1;
+    game.undone.push(step.mv);
+    debug_validate(game, step.mv, fresh);
+    Some(step.mv)
+}
+
+/// Plays the last undone move again, returning it. None if there is nothing to redo
+pub fn redo(game: &mut Game) -> Option<Move> {
+    let mv = game.undone.pop()?;
+    match apply_move(game, mv) {
+        Ok(_) => Some(mv),
+        Err(_) => {
+            // The board was changed behind our back, the rest of the redo list is meaningless
+            game.undone.clear();
+            None
+        },
+    }
+}
+
+fn check_pile(pile: usize) -> Result<(), MoveError> {
+    if pile < 7 { Ok(()) } else { Err(MoveError::NoSuchPile(pile)) }
+}
+
+fn check_found(found: usize) -> Result<(), MoveError> {
+    if found < 4 { Ok(()) } else { Err(MoveError::NoSuchFoundation(found)) }
+}
+
+// Make sure that there is a card where we want to take from
+fn check_depth(game: &Game, src_pile: usize, src_depth: usize) -> Result<(), MoveError> {
+    let available = game.piles[src_pile].len();
+    if src_depth == 0 {
+        Err(MoveError::ZeroDepth)
+    } else if available < src_depth {
+        Err(MoveError::NotEnoughCards { available, requested: src_depth })
     } else {
-        move_pile_pile(game, src_pile, src_depth, dest_pile)
+        Ok(())
     }
+}
 
+// Make sure the cards taken from a pile form a face up run that could have been built there
+fn check_run(run: &[Card]) -> Result<(), MoveError> {
+    if run.iter().any(|c| !c.up) {
+        Err(MoveError::FaceDownInRun)
+    } else if run.windows(2).any(|w| !suit_alternates(&w[0], &w[1]) || !number_match_desc(Some(&w[0]), &w[1])) {
+        Err(MoveError::BrokenRun)
+    } else {
+        Ok(())
+    }
 }
-pub fn move_hand_found(game: &mut Game, dest_pile: usize) -> bool {
+
+// The card on top of the hand
+fn hand_card(game: &Game) -> Result<Card, MoveError> {
+    game.hand.last().cloned().ok_or(MoveError::NoCardInHand)
+}
+
+// Checks that card may be placed on the given foundation
+fn check_found_target(game: &Game, card: &Card, dest: usize) -> Result<(), MoveError> {
     // Moving to a foundation:
     //   - Suit must match
     //   - number must be one higher
-    let card = game.hand.last().unwrap().clone();
-    if !suit_match(dest_pile, &card) {
-        term::printw("Error: Suits must match on the foundations\n");
-        return false;
-    } else if !number_match_asc(game.foundations[dest_pile - 7].len(), &card) {
-        term::printw("Error: Numbers must ascend on the foundations\n");
-        return false;
+    if !suit_match(&game.foundations[dest], card) {
+        Err(MoveError::SuitMismatch)
+    } else if !number_match_asc(game.foundations[dest].len(), card) {
+        Err(MoveError::NotAscending)
+    } else {
+        Ok(())
     }
-
-    let card = game.hand.pop().unwrap();
-    game.foundations[dest_pile - 7].push(card);
-    game.score += 5;
-    true
 }
 
-
-pub fn move_hand_pile(game: &mut Game, dest_pile: usize) -> bool {
+// Checks that card may be placed on the bottom of the given pile
+fn check_pile_target(game: &Game, card: &Card, dest: usize) -> Result<(), MoveError> {
     // Moving to a pile:
     //   - suit must alternate
     //   - number must be one lower
-    let card = game.hand.last().unwrap().clone();
-    if card.number == 13 {
+    //   - only kings to empty piles, unless the rules allow any card
+    let target = game.piles[dest].last();
+    if card.rank == Rank::King {
         // King:
         //   - Destination pile must be empty
-        if !game.piles[dest_pile].is_empty() {
-            term::printw("Error: Kings can only be moved to empty piles\n");
-            return false;
-        }
-    } else if game.piles[dest_pile].is_empty() {
-        term::printw("Error: Only Kings can move to empty piles\n");
-        return false;
-    } else if !suit_alternates(&card, game.piles[dest_pile].last().unwrap()) {
-        term::printw("Error: Suits must alternate on the piles\n");
-        return false;
-    } else if !number_match_desc(game.piles[dest_pile].last(), &card) {
-        term::printw("Error: Numbers must decrease by one on the piles\n");
-        return false;
-    }
-    
+        if target.is_some() {
+            return Err(MoveError::KingToNonEmpty);
+        }
+    } else {
+        match target {
+            None => if game.rules.empty_column == EmptyColumn::KingsOnly {
+                return Err(MoveError::NonKingToEmpty);
+            },
+            Some(c) => if !suit_alternates(card, c) {
+                return Err(MoveError::SuitsMustAlternate);
+            } else if !number_match_desc(Some(c), card) {
+                return Err(MoveError::NotDescending);
+            },
+        }
+    }
+    Ok(())
+}
+
+// The move_ functions below carry out a move that check_move has already accepted
+
+fn move_hand_found(game: &mut Game, dest: usize) -> MoveOutcome {
     let card = game.hand.pop().unwrap();
-    game.piles[dest_pile].push(reveal(&card));
-    true
+    game.foundations[dest].push(card);
+    outcome(1, false)
 }
-pub fn move_pile_found(game: &mut Game, src_pile: usize, src_depth: usize, dest_pile: usize) -> bool {
-    // Trying to move to a foundation:
-    //   - Only one card allowed
-    //   - Suit must match
-    //   - Number must be one higher
-    let card = (game.piles[src_pile][game.piles[src_pile].len() - src_depth]).clone();
-    if src_depth != 1 {
-        term::printw("Error: Cannot move more than one card to foundation\n");
-        return false;
-    } else if !suit_match(dest_pile, &card) {
-        term::printw("Error: Suits must match on the foundation\n");
-        return false;
-    } else if !number_match_asc(game.foundations[dest_pile - 7].len(), &card) {
-        term::printw("Error: Numbers must ascend by one on the foundation\n");
-        return false;
-    }
-
-    // Validation has passed:
-    let card = game.piles[src_pile].pop().unwrap();
-    game.foundations[dest_pile - 7].push(card);
-    // Make sure the last card in the pile is revealed if necessary
-    match game.piles[src_pile].last_mut() {
-        None => {},
-        Some(c) => {c.up = true}
-    };
-    game.score += 5;
-    true
+
+fn move_hand_pile(game: &mut Game, dest: usize) -> MoveOutcome {
+    let card = game.hand.pop().unwrap();
+    game.piles[dest].push(reveal(&card));
+    outcome(1, false)
+}
+
+fn move_pile_found(game: &mut Game, src: usize, dest: usize) -> MoveOutcome {
+    let card = game.piles[src].pop().unwrap();
+    game.foundations[dest].push(card);
+    let revealed = reveal_last(&mut game.piles[src]);
+    outcome(1, revealed)
 }
 
-pub fn move_pile_pile(game: &mut Game, src_pile: usize, src_depth: usize, dest_pile: usize) -> bool {
+fn move_found_pile(game: &mut Game, src: usize, dest: usize) -> MoveOutcome {
+    let card = game.foundations[src].pop().unwrap();
+    game.piles[dest].push(card);
+    outcome(1, false)
+}
 
-    // Trying to move to another pile (we already know the source stack exists):
+fn move_pile_pile(game: &mut Game, src: usize, depth: usize, dest: usize) -> MoveOutcome {
+    let split_index = game.piles[src].len() - depth;
+    let mut cards = game.piles[src].split_off(split_index);
+    game.piles[dest].append(&mut cards);
+    let revealed = reveal_last(&mut game.piles[src]);
+    outcome(depth, revealed)
+}
 
-    let card = (game.piles[src_pile][game.piles[src_pile].len() - src_depth]).clone();
+fn move_draw(game: &mut Game) -> MoveOutcome {
+    let mut moved = 0;
+    while moved < game.rules.draw_count && !game.side_deck.is_empty() {
+        game.hand.push(reveal(&game.side_deck.pop().unwrap()));
+        moved += 1;
+    }
+    outcome(moved, false)
+}
 
-    if card.number == 13 {
-        // King:
-        //   - Destination pile must be empty
-        if !game.piles[dest_pile].is_empty() {
-            term::printw("Error: Kings can only be moved to empty piles\n");
-            return false;
-        }
-    } else if game.piles[dest_pile].is_empty() {
-            term::printw("Error: Cannot move non-king to empty pile\n");
-            return false;
-    } else if !suit_alternates(&card, game.piles[dest_pile].last().unwrap()) {
-        term::printw("Error: Suit colours must alternate on piles\n");
-        return false;
-    } else if !number_match_desc(game.piles[dest_pile].last(), &card) {
-        term::printw("Error: Numbers must decrease by one on piles\n");
-        return false;
-    }
-
-    // Validation has passed:
-    let split_index = game.piles[src_pile].len() - src_depth;
-    let mut cards = game.piles[src_pile].split_off(split_index);
-    game.piles[dest_pile].append(&mut cards);
-    match game.piles[src_pile].last_mut() {
-        None => {},
-        Some(c) => {c.up = true}
-    };
-    true
+fn move_recycle(game: &mut Game) -> MoveOutcome {
+    // The side deck is always face down, so that undoing a draw knows how to put cards back
+    let cards = game.hand.len();
+    game.side_deck = game.hand.drain(..).rev().map(|c| Card { up: false, ..c }).collect();
+    game.recycles += 1;
+    outcome(cards, false)
+}
+
+// The score change is filled in by apply_move once the move has been made and scored
+fn outcome(cards: usize, revealed: bool) -> MoveOutcome {
+    MoveOutcome { cards, revealed, score: 0 }
 }
 
-// Returns whether the hand was reset
+// Make sure the last card in the pile is revealed if necessary. Returns whether it was flipped
+fn reveal_last(pile: &mut [Card]) -> bool {
+    match pile.last_mut() {
+        Some(ref mut c) if !c.up => { c.up = true; true },
+        _ => false,
+    }
+}
+
+// Turns the last card of the pile back face down
+fn hide_last(pile: &mut [Card]) {
+    if let Some(c) = pile.last_mut() {
+        c.up = false;
+    }
+}
+
+/// Draws from the side deck, or turns the hand back over once the side deck is exhausted.
+/// Returns whether the hand was reset, or the rules allow no drawing at all
 pub fn draw(game: &mut Game) -> bool {
     if game.side_deck.is_empty() {
-        game.side_deck = game.hand.clone();
-        game.side_deck.reverse();
-        game.hand = vec![];
+        // An empty hand has nothing to turn over, but the side deck has still been run through
+        let _ = make_move(game, Move::Recycle);
         true
     } else {
-        let mut moved = 0;
-        while moved < 3 && !game.side_deck.is_empty() {
-            game.hand.push(reveal(&game.side_deck.pop().unwrap()));
-            moved += 1;
-        }
-        false
+        // Refused only when no cards are drawn at a time, which is as far as the deal will go
+        make_move(game, Move::Draw).is_err()
     }
-}    
+}
 
-pub fn suit_match(dest: usize, card: &Card) -> bool {
-    match dest {
-        7 => card.suit == 'H',
-        8 => card.suit == 'S',
-        9 => card.suit == 'D',
-        10 => card.suit == 'C',
-        _ => false,
+/// Whether the card's suit belongs on the given foundation. Empty foundations take any suit
+pub fn suit_match(found: &[Card], card: &Card) -> bool {
+    match found.last() {
+        None => true,
+        Some(c) => c.suit == card.suit,
     }
 }
 
 pub fn suit_alternates(a: &Card, b: &Card) -> bool {
-    match a.suit {
-        'H' | 'D' => { b.suit == 'C' || b.suit == 'S'},
-        'C' | 'S' => { b.suit == 'H' || b.suit == 'D'},
-        _ => false,
-    }
+    a.colour() != b.colour()
 }
 
 pub fn number_match_asc(base: usize, card: &Card) -> bool {
-    card.number - 1 == base as u8
+    card.rank.value() as usize == base + 1
 }
 
 pub fn number_match_desc(dest: Option<&Card>, new: &Card) -> bool {
     match dest {
-        None => new.number == 13,
-        Some(c) => c.number - 1 == new.number,
+        None => new.rank == Rank::King,
+        Some(c) => c.rank.prev() == Some(new.rank),
     }
 }
 
+/// All 52 cards face down, in suit then rank order
 pub fn deck() -> Vec<Card> {
     let mut deck = vec![];
-    for suit in &['H', 'D', 'C', 'S'] {
-        for i in 1..14 {
-            deck.push(card(suit, i));
+    for suit in &Suit::ALL {
+        for rank in &Rank::ALL {
+            deck.push(card(*suit, *rank));
         }
     }
     deck
 }
 
-pub fn card(suit: &char, number: u8) -> Card {
-    Card { suit: *suit, number: number, up: false}
+pub fn card(suit: Suit, rank: Rank) -> Card {
+    Card { suit, rank, up: false }
 }
 
-pub fn card_clone(card: &Card) -> Card {
-    Card { suit: card.suit, number: card.number, up: card.up}
+pub fn reveal(card: &Card) -> Card {
+    Card { up: true, ..*card }
 }
 
-pub fn reveal(card: &Card) -> Card {
-    Card { suit: card.suit, number: card.number, up: true}
+/// Fixed width form used when drawing the board
+pub fn card_string(card: &Card) -> String {
+    format!("{:>3}", card)
 }
 
-pub fn card_str_disp(card: &Card) -> String {
-    if card.up { card_string(card) }
-    else { " XX".to_string() }
+/// Shuffles any deck with a random seed
+pub fn shuffle(deck: &[Card]) -> Vec<Card> {
+    let mut rng = SplitMix64::new(rand::thread_rng().gen());
+    shuffle_with(deck, &mut rng)
 }
 
-pub fn card_string(card: &Card) -> String {
-    if card.number == 1 {
-        format!(" A{}", card.suit)
-    } else if card.number == 11 {
-        format!(" J{}", card.suit)
-    } else if card.number == 12 {
-        format!(" Q{}", card.suit)
-    } else if card.number == 13 {
-        format!(" K{}", card.suit)
-    } else {
-        format!("{:2}{}", card.number, card.suit)
+/// Shuffles the standard deck for deal number seed. The result depends only on the seed: deck()
+/// order, SplitMix64 and shuffle_with below are fixed, and deal() takes cards from the end.
+/// Changing any of them renumbers every deal.
+pub fn shuffle_seeded(seed: u64) -> Vec<Card> {
+    shuffle_with(&deck(), &mut SplitMix64::new(seed))
+}
+
+// Fisher-Yates, walking down from the last card and swapping each with one at or below it
+fn shuffle_with(deck: &[Card], rng: &mut SplitMix64) -> Vec<Card> {
+    let mut res = deck.to_vec();
+    for i in (1..res.len()).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        res.swap(i, j);
     }
+    res
 }
 
-pub fn shuffle(deck: &[Card]) -> Vec<Card> {
-    let mut rng = rand::thread_rng();
-    let mut res = vec![];
-    let mut used = [false; 52];
+/// The SplitMix64 generator (Steele, Lea and Flood, 2014). Implemented here rather than taken from
+/// rand so that deal numbers cannot change with a dependency upgrade.
+pub struct SplitMix64 {
+    state: u64,
+}
 
-    for _card in 0..52 {
-        let mut choice: usize = rng.gen::<usize>() % 52;
-        while used[choice] {
-            choice = rng.gen::<usize>() % 52;
-        }
-        res.push(card_clone(&deck[choice]));
-        used[choice] = true;
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
     }
 
-    res
-}
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
 
-pub fn set_colour(card: &Card) {
-    if card.up {
-       match card.suit {
-           'H' | 'D' => {
-               term::attron(term::COLOR_PAIR(PAIR_RED));
-           },
-           'C' | 'S' => {
-               term::attron(term::COLOR_PAIR(PAIR_BLK));
-           },
-           _ => ()
-       }
+    /// Uniform in 0..n. Outputs from the incomplete final block of n are rejected so that no value
+    /// is favoured.
+    pub fn below(&mut self, n: u64) -> u64 {
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let r = self.next_u64();
+            if r >= threshold {
+                return r % n;
+            }
+        }
     }
 }
 
-pub fn clear_colour(card: &Card) {
-    if card.up {
-       match card.suit {
-           'H' | 'D' => {
-               term::attroff(term::COLOR_PAIR(PAIR_RED));
-           },
-           'C' | 'S' => {
-               term::attroff(term::COLOR_PAIR(PAIR_BLK));
-           },
-           _ => ()
-       }
+// Neither the scoring scheme nor an Instant can be written out as they are, so Game's serde
+// support goes through these instead
+#[cfg(feature = "serde")]
+mod serde_fields {
+    use std::time;
+    use serde::Deserialize;
+
+    use super::{Game, Card, Step, Move, Rules, default_scoring};
+    use scoring::Scoring;
+    use zobrist::rehash;
+
+    // Game as it is read back, everything but the hash, which is worked out from the cards
+    #[derive(Deserialize)]
+    pub struct Loaded {
+        piles: [Vec<Card>; 7],
+        side_deck: Vec<Card>,
+        hand: Vec<Card>,
+        foundations: [Vec<Card>; 4],
+        score: isize,
+        moves: usize,
+        history: Vec<Step>,
+        undone: Vec<Move>,
+        seed: Option<u64>,
+        rules: Rules,
+        recycles: usize,
+        #[serde(default)]
+        auto_play: bool,
+        #[serde(default = "default_scoring", with = "scoring")]
+        scoring: &'static dyn Scoring,
+        #[serde(rename = "elapsed_secs", default = "time::Instant::now", with = "elapsed")]
+        dealt: time::Instant,
+    }
+
+    impl From<Loaded> for Game {
+        fn from(l: Loaded) -> Game {
+            let mut game = Game {
+                piles: l.piles,
+                side_deck: l.side_deck,
+                hand: l.hand,
+                foundations: l.foundations,
+                score: l.score,
+                moves: l.moves,
+                history: l.history,
+                undone: l.undone,
+                seed: l.seed,
+                rules: l.rules,
+                recycles: l.recycles,
+                auto_play: l.auto_play,
+                hash: 0,
+                scoring: l.scoring,
+                dealt: l.dealt,
+            };
+            rehash(&mut game);
+            game
+        }
+    }
+
+    pub mod scoring {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use serde::de::Error;
+        use scoring::{Scoring, scheme_by_name};
+
+        pub fn serialize<S: Serializer>(scoring: &&'static dyn Scoring, s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_str(scoring.name())
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<&'static dyn Scoring, D::Error> {
+            let name = String::deserialize(d)?;
+            scheme_by_name(&name).ok_or_else(|| D::Error::custom(format!("Unknown scoring {:?}", name)))
+        }
+    }
+
+    // Stored as the whole seconds since the deal and read back as that long before now
+    pub mod elapsed {
+        use std::time;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(dealt: &time::Instant, s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_u64(dealt.elapsed().as_secs())
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<time::Instant, D::Error> {
+            let elapsed = time::Duration::from_secs(u64::deserialize(d)?);
+            let now = time::Instant::now();
+            Ok(now.checked_sub(elapsed).unwrap_or(now))
+        }
     }
 }
-    
-struct Dur {
-    dur: time::Duration,
-}
-impl fmt::Display for Dur {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut secs = self.dur.as_secs();
 
-        let hrs = secs/3600;
-        secs -= hrs * 3600;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialised_game_is_hashed() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 3);
+        make_move(&mut game, Move::Draw).unwrap();
+        let json = serde_json::to_string(&game).unwrap();
+        let mut loaded: Game = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.hash, game.hash);
+        assert_eq!(loaded.validate(), Ok(()));
+        make_move(&mut loaded, Move::Draw).unwrap();
+    }
+
+    #[test]
+    fn cards_read_back_as_written() {
+        for card in deck() {
+            assert_eq!(card.to_string().parse(), Ok(card));
+            assert_eq!(card.to_string().to_ascii_lowercase().parse(), Ok(card));
+        }
+        assert_eq!("10H".parse::<Card>().map(|c| c.index()), Ok(Suit::Hearts as usize * 13 + 9));
+        assert_eq!("AS".parse(), Ok(card(Suit::Spades, Rank::Ace)));
+    }
+
+    #[test]
+    fn odd_card_forms_are_refused() {
+        for s in ["", "H", "10", "TH", "01H", "+1H", "1H", "11H", "0H", "AX", "A H", "AHS", "10HH"] {
+            assert_eq!(s.parse::<Card>(), Err(ParseCardError(s.to_string())), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn moves_read_back_as_written() {
+        let mut moves = vec![Move::Draw, Move::Recycle];
+        for pile in 0..7 {
+            moves.push(Move::WasteToTableau { dest: pile });
+            for found in 0..4 {
+                moves.push(Move::WasteToFoundation { dest: found });
+                moves.push(Move::TableauToFoundation { src: pile, dest: found });
+                moves.push(Move::FoundationToTableau { src: found, dest: pile });
+            }
+            for dest in 0..7 {
+                for depth in 1..14 {
+                    moves.push(Move::TableauToTableau { src: pile, depth, dest });
+                }
+            }
+        }
+        for mv in moves {
+            assert_eq!(Move::parse(&mv.to_string()), Ok(mv));
+            assert_eq!(Move::parse(&mv.to_string().to_ascii_lowercase()), Ok(mv));
+        }
+        assert_eq!(Move::parse("3>5x1"), Ok(Move::TableauToTableau { src: 2, depth: 1, dest: 4 }));
+    }
+
+    #[test]
+    fn bad_moves_are_refused() {
+        for s in ["", ">", "3>F1x2", "W>1x2", "W>W", "F1>F2", "F1>1x2", "8>1", "0>1", "1>8", "F5>1",
+                  "F0>1", "W>F5", "H>3", "3>H", "W>D", "3>5x", "3>5xA", "3>5x0", "3>5x+2", "+3>5", "F+1>5", "DD", "3-5"] {
+            assert_eq!(Move::parse(s), Err(ParseMoveError(s.to_string())), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn suits_name_foundations_in_a_position() {
+        let mut game = game_init();
+        game.foundations[2].push(Card { up: true, ..card(Suit::Diamonds, Rank::Ace) });
+        assert_eq!(game.parse_move("D"), Ok(Move::Draw));
+        assert_eq!(game.parse_move("W>D"), Ok(Move::WasteToFoundation { dest: 2 }));
+        assert_eq!(game.parse_move("D>4"), Ok(Move::FoundationToTableau { src: 2, dest: 3 }));
+        // An unstarted suit goes to the first empty foundation
+        assert_eq!(game.parse_move("3>h"), Ok(Move::TableauToFoundation { src: 2, dest: 0 }));
+    }
+
+    fn cards(s: &str) -> Vec<Card> {
+        s.split_whitespace().map(|c| c.parse().unwrap()).collect()
+    }
+
+    // Deal numbers are shared between players and kept in replays, so these must never change
+    #[test]
+    fn seeded_shuffles_are_fixed() {
+        assert_eq!(shuffle_seeded(0), cards("8C JD 9H 2C 2S QS 6C 10H 3S AH JH 7H 9D 8H QC KD 5D 2H \
+            3D 5H AD 6H 4C 10S 5S 8D 8S AS QD 10C QH JS 3C 4S 2D 9S 3H KS AC 7C 9C 7S JC 6D KC 4H 7D \
+            5C 6S 4D KH 10D"));
+        assert_eq!(shuffle_seeded(1), cards("7S 9H 4S 8D 8S 5C JS 6S 9C JH 5S KS 3H 2S JD 5D 7D 4D \
+            2H AS 6C KH KD 10D 10C JC 10S 8C QH 4C 6H 7H AC 5H AD KC 6D QC QD QS 9S 3S 3C AH 4H 2D 8H \
+            10H 3D 2C 9D 7C"));
+        assert_eq!(shuffle_seeded(12345), cards("6D 9D 6C 9C AC 10H KS 8C 7S 10S 2S 5H 8S 3S QS 7C \
+            8H 3H 9H 9S 4H 4S JH QC 10C 3C AS JS 8D 5S JC 4C 10D KC AH 3D 7H 6S AD KH QD 4D 2H 5C KD \
+            5D QH 2D 7D 6H JD 2C"));
+    }
+
+    #[test]
+    fn seeded_deals_are_fixed() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 12345);
+        let tops: Vec<String> = game.piles.iter().map(|p| p.last().unwrap().to_string()).collect();
+        assert_eq!(tops.join(" "), "2C 6H QH 2H 6S 4C 10C");
+        assert_eq!(game.side_deck.last().map(|c| c.to_string()), Some("QC".to_string()));
+        assert_eq!(game.side_deck.len(), 24);
+    }
+
+    #[test]
+    fn time_is_charged_down_to_the_floor() {
+        let mut game = game_init();
+        game.scoring = &::scoring::Standard;
+        game_restart_with_seed(&mut game, 3);
+        game.score = 25;
+        game.dealt = time::Instant::now() - time::Duration::from_secs(65);
+        assert_eq!(timed_score(&game), 13);
+        game.dealt = time::Instant::now() - time::Duration::from_secs(600);
+        assert_eq!(timed_score(&game), 0);
+        assert_eq!(game_result(&game).score, 0);
+        assert_eq!(game.score, 25);
+    }
+
+    // Everything undo has to put back
+    #[derive(Debug, PartialEq)]
+    struct Board {
+        piles: Vec<Vec<Card>>,
+        hand: Vec<Card>,
+        side_deck: Vec<Card>,
+        foundations: Vec<Vec<Card>>,
+        recycles: usize,
+        score: isize,
+        moves: usize,
+    }
+
+    fn board(game: &Game) -> Board {
+        Board {
+            piles: game.piles.to_vec(),
+            hand: game.hand.clone(),
+            side_deck: game.side_deck.clone(),
+            foundations: game.foundations.to_vec(),
+            recycles: game.recycles,
+            score: game.score,
+            moves: game.moves,
+        }
+    }
+
+    #[test]
+    fn undo_goes_back_one_move_at_a_time() {
+        let mut game = game_init();
+        game.scoring = &::scoring::Standard;
+        game_restart_with_seed(&mut game, 4);
+        let mut player = ::player::create_player(game.clone());
+        let mut n = 0;
+        while n < 400 && ::player::play_one_move(&mut player) {
+            n += 1;
+        }
+        let history = &player.game.history;
+        assert!(history.iter().any(|step| step.outcome.revealed));
+        assert!(history.iter().any(|step| step.mv == Move::Recycle));
+        assert!(history.iter().any(|step| step.outcome.score < 0));
+
+        // The same moves again, one at a time, keeping the board after each
+        let mut boards = vec![board(&game)];
+        for step in history {
+            make_move(&mut game, step.mv).unwrap();
+            boards.push(board(&game));
+        }
+        let last = board(&game);
+        while let Some(mv) = undo(&mut game) {
+            boards.pop();
+            assert_eq!(&board(&game), boards.last().unwrap(), "undoing {}", mv);
+        }
+        assert_eq!(boards.len(), 1);
+        assert_eq!((game.recycles, game.score, game.moves), (0, 0, 0));
+
+        while redo(&mut game).is_some() {}
+        assert_eq!(board(&game), last);
+    }
+
+    #[test]
+    fn runs_with_face_down_cards_are_refused() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 12345);
+        let before = board(&game);
+        for dest in [0, 2] {
+            let mv = Move::TableauToTableau { src: 1, depth: 2, dest };
+            assert_eq!(make_move(&mut game, mv), Err(MoveError::FaceDownInRun));
+        }
+        assert_eq!(board(&game), before);
+        assert!(game.history.is_empty());
+    }
+
+    #[test]
+    fn broken_runs_are_refused() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 12345);
+        // Turn up the card under a pile's top card where the two do not make a run
+        let src = (1..7).find(|&i| {
+            let pile = &game.piles[i];
+            let (below, top) = (&pile[pile.len() - 2], &pile[pile.len() - 1]);
+            !(suit_alternates(below, top) && number_match_desc(Some(below), top))
+        }).unwrap();
+        let under = game.piles[src].len() - 2;
+        game.piles[src][under].up = true;
+        let before = board(&game);
+        let mv = Move::TableauToTableau { src, depth: 2, dest: if src == 1 { 2 } else { 1 } };
+        assert_eq!(make_move(&mut game, mv), Err(MoveError::BrokenRun));
+        assert_eq!(board(&game), before);
+        assert!(game.history.is_empty());
+    }
+
+    #[test]
+    fn each_broken_invariant_is_named() {
+        let fresh = || {
+            let mut game = game_init();
+            game_restart_with_seed(&mut game, 12345);
+            make_move(&mut game, Move::Draw).unwrap();
+            game
+        };
+        let broken = |f: &dyn Fn(&mut Game)| {
+            let mut game = fresh();
+            f(&mut game);
+            game.validate().unwrap_err()
+        };
+        assert_eq!(fresh().validate(), Ok(()));
+
+        assert_eq!(broken(&|g| { g.side_deck.pop(); }), InvariantViolation::CardCount(51));
+        assert_eq!(broken(&|g| g.side_deck[0] = g.side_deck[1]),
+                   InvariantViolation::DuplicateCard(fresh().side_deck[1]));
+        assert_eq!(broken(&|g| g.side_deck[0].up = true), InvariantViolation::FaceUpInSideDeck);
+        assert_eq!(broken(&|g| g.hand[0].up = false), InvariantViolation::FaceDownInHand);
+        assert_eq!(broken(&|g| g.piles[3].last_mut().unwrap().up = false),
+                   InvariantViolation::TopCardFaceDown { pile: 3 });
+        assert_eq!(broken(&|g| g.piles[6][2].up = true), InvariantViolation::FaceDownOnFaceUp { pile: 6 });
+        // The 2C on top of the first pile is put on the 6H of the second
+        assert_eq!(broken(&|g| {
+            let card = g.piles[0].pop().unwrap();
+            g.piles[1].push(card);
+        }), InvariantViolation::BrokenRun { pile: 1, index: 2 });
+        // The 2C goes to a foundation with no Ace under it
+        assert_eq!(broken(&|g| {
+            let card = g.piles[0].pop().unwrap();
+            g.foundations[2].push(card);
+        }), InvariantViolation::FoundationOutOfOrder { foundation: 2, index: 0 });
+        assert_eq!(broken(&|g| {
+            let card = g.side_deck.pop().unwrap();
+            g.foundations[1].push(card);
+        }), InvariantViolation::FaceDownOnFoundation { foundation: 1 });
+        assert_eq!(broken(&|g| g.hash ^= 1), InvariantViolation::StaleHash);
+    }
+
+    #[test]
+    fn a_new_move_clears_redo() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 4);
+        make_move(&mut game, Move::Draw).unwrap();
+        make_move(&mut game, Move::Draw).unwrap();
+        assert_eq!(undo(&mut game), Some(Move::Draw));
+        assert_eq!(game.undone, vec![Move::Draw]);
+        make_move(&mut game, Move::Draw).unwrap();
+        assert!(game.undone.is_empty());
+        assert_eq!(redo(&mut game), None);
+    }
+
+    // A game with nothing in the hand or side deck, each pile given as its face down cards, a
+    // slash, then its face up cards
+    fn layout(piles: [&str; 7], foundations: [&str; 4]) -> Game {
+        let mut game = game_init();
+        for (pile, text) in game.piles.iter_mut().zip(piles.iter()) {
+            let (down, up) = text.split_at(text.find('/').unwrap());
+            pile.extend(cards(down));
+            pile.extend(cards(&up[1..]).into_iter().map(|c| reveal(&c)));
+        }
+        for (found, text) in game.foundations.iter_mut().zip(foundations.iter()) {
+            found.extend(cards(text).into_iter().map(|c| reveal(&c)));
+        }
+        ::zobrist::rehash(&mut game);
+        assert_eq!(game.validate(), Ok(()));
+        game
+    }
+
+    const FOUNDATIONS: [&str; 4] = ["AH 2H 3H 4H 5H", "AS 2S 3S 4S 5S 6S", "AD 2D 3D", "AC 2C"];
+
+    #[test]
+    fn chains_off_the_foundations_keep_a_deal_going() {
+        // 6S then 5H can come down onto 7D, and then 4C onto them, turning over the 3C
+        let mut game = layout(["6H 7H 8H 9H 10H / 7D", "QH 7S 8S 9S 10S / KH", "JS QS 4D 5D 6D / KS",
+                               "8D 9D 10D JD QD / KD", "5C 6C 7C 8C 9C / KC", "10C JC QC / JH",
+                               "3C / 4C"], FOUNDATIONS);
+        assert_eq!(game_status(&game), GameStatus::InProgress);
+        for mv in ["F2>1", "F1>1"] {
+            make_move(&mut game, mv.parse().unwrap()).unwrap();
+        }
+        assert!(make_move(&mut game, "7>1".parse().unwrap()).unwrap().revealed);
+        assert_eq!(game_status(&game), GameStatus::InProgress);
+    }
+
+    #[test]
+    fn chains_leading_nowhere_lose() {
+        // As above but with the 4C buried, so the 6S and 5H can come down to no purpose
+        let game = layout(["6H 7H 8H 9H 10H / 7D", "QH 7S 8S 9S 10S / KH", "JS QS 4D 5D 6D / KS",
+                           "8D 9D 10D JD QD / KD", "5C 6C 4C 8C 7C / KC", "10C JC QC / JH",
+                           "3C / 9C"], FOUNDATIONS);
+        assert_eq!(game_status(&game), GameStatus::Lost);
+    }
+
+    #[test]
+    fn new_deals_are_in_progress() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 3);
+        assert_eq!(game_status(&game), GameStatus::InProgress);
+    }
+
+    // The 3H waits on the 2S, the black Two not yet up
+    fn waiting_on_a_two() -> Game {
+        layout(["4H 5H 6H 7H 8H 10D / 3H", "9H 10H JH 3S 4S 9C / 2S", "5S 6S 7S 8S 9S / KH",
+                "10S JS QS 3C 4C / KS", "5C 6C 7C 8C 10C / KC", "JC QC 3D 4D 5D / KD",
+                "6D 7D 8D 9D JD QD / QH"], ["AH 2H", "AS", "AC 2C", "AD 2D"])
+    }
+
+    #[test]
+    fn cards_wait_for_both_lower_cards_of_the_other_colour() {
+        let mut game = waiting_on_a_two();
+        let three = *game.piles[0].last().unwrap();
+        assert!(!safe_to_foundation(&game, &three));
+        assert!(safe_to_foundation(&game, game.piles[1].last().unwrap()));
+        make_move(&mut game, "2>F2".parse().unwrap()).unwrap();
+        assert!(safe_to_foundation(&game, &three));
+    }
+
+    #[test]
+    fn auto_play_off_moves_nothing() {
+        let mut game = waiting_on_a_two();
+        make_move(&mut game, "7>4".parse().unwrap()).unwrap();
+        assert_eq!(game.history.len(), 1);
+        assert_eq!(game.foundations[1].len(), 1);
+        assert_eq!(auto_play_safe(&mut game), 2);
+    }
+
+    #[test]
+    fn auto_played_moves_undo_one_at_a_time() {
+        let mut game = waiting_on_a_two();
+        game.auto_play = true;
+        make_move(&mut game, "7>4".parse().unwrap()).unwrap();
+        let moves: Vec<String> = game.history.iter().map(|step| step.mv.to_string()).collect();
+        assert_eq!(moves, ["7>4", "2>F2", "1>F1"]);
+
+        assert_eq!(undo(&mut game), Some("1>F1".parse().unwrap()));
+        assert_eq!(game.piles[0].last().map(|c| c.to_string()), Some("3H".to_string()));
+        assert_eq!(undo(&mut game), Some("2>F2".parse().unwrap()));
+        assert_eq!(game.piles[1].last().map(|c| c.to_string()), Some("2S".to_string()));
+        assert_eq!(undo(&mut game), Some("7>4".parse().unwrap()));
+        assert_eq!(board(&game), board(&waiting_on_a_two()));
+    }
+
+    // Every card face up, the Jacks, Queens and Kings still to go
+    fn nearly_done() -> Game {
+        layout(["/ KH QS JH", "/ KS QH JS", "/ KC QD JC", "/ KD QC JD", "/", "/", "/"],
+               ["AH 2H 3H 4H 5H 6H 7H 8H 9H 10H", "AS 2S 3S 4S 5S 6S 7S 8S 9S 10S",
+                "AC 2C 3C 4C 5C 6C 7C 8C 9C 10C", "AD 2D 3D 4D 5D 6D 7D 8D 9D 10D"])
+    }
+
+    #[test]
+    fn autocomplete_needs_every_card_showing() {
+        assert!(can_autocomplete(&nearly_done()));
+
+        let mut hidden = nearly_done();
+        hidden.piles[0][0].up = false;
+        assert!(!can_autocomplete(&hidden));
+
+        let mut in_hand = nearly_done();
+        let card = in_hand.piles[0].pop().unwrap();
+        in_hand.hand.push(card);
+        assert!(!can_autocomplete(&in_hand));
+
+        let mut in_stock = nearly_done();
+        let card = in_stock.piles[0].pop().unwrap();
+        in_stock.side_deck.push(Card { up: false, ..card });
+        assert!(!can_autocomplete(&in_stock));
+        assert_eq!(autocomplete(&mut in_stock), 0);
+    }
+
+    #[test]
+    fn autocomplete_wins() {
+        let mut game = nearly_done();
+        assert_eq!(autocomplete(&mut game), 12);
+        assert!(game_won(&game));
+        assert!(!can_autocomplete(&game));
+        assert_eq!(game.history.len(), 12);
+    }
+
+    #[test]
+    fn drawing_no_cards_is_refused() {
+        let mut game = game_init_with_rules(Rules { draw_count: 0, ..Rules::default() });
+        game_restart_with_seed(&mut game, 3);
+        assert_eq!(make_move(&mut game, Move::Draw), Err(MoveError::NoCardsDrawn));
+        assert!(!game.legal_moves().contains(&Move::Draw));
+        assert_eq!(game.moves, 0);
+        assert_eq!(game_status(&game), GameStatus::Lost);
+        assert!(draw(&mut game));
+    }
 
-        let mins = secs/60;
-        secs -= mins * 60;
-        write!(f, "{:02}:{:02}:{:02}", hrs, mins, secs)
+    #[test]
+    fn stale_hash_does_not_stop_play() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 3);
+        game.hash = 0;
+        make_move(&mut game, Move::Draw).unwrap();
+        undo(&mut game).unwrap();
+        assert_eq!(game.validate(), Err(InvariantViolation::StaleHash));
     }
 }
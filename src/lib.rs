@@ -0,0 +1,22 @@
+//! A Klondike solitaire engine with a simple automatic player.
+//!
+//! `game` holds the rules and the board, `player` plays a game by itself, `scoring` has the
+//! scoring schemes a game can be played under and `session` keeps totals over many deals.
+//! `replay` records deals and plays them back, `zobrist` hashes positions and `packed` stores
+//! them in 52 bytes. None of it draws anything; the `solitaire` binary
+//! is one front-end built on top.
+//!
+//! With the `serde` feature, cards, moves and positions can be serialised. See `game::Game` for
+//! what a position holds.
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+pub mod game;
+pub mod packed;
+pub mod player;
+pub mod replay;
+pub mod scoring;
+pub mod session;
+pub mod zobrist;
@@ -1,273 +1,230 @@
+// Terminal front-end for the solitaire library: the profiling runs and a game for a human to play
+#[cfg(feature = "tui")]
 extern crate ncurses;
-mod game;
-mod player;
+extern crate solitaire;
+#[cfg(feature = "tui")]
+extern crate serde_json;
+mod display;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "tui")]
+mod save;
 
 use std::time;
 use std::thread::sleep;
+use std::{env, fs, panic, process};
 
-use game::*;
-use std::char;
+use solitaire::game::*;
+use solitaire::player;
+use solitaire::replay::{self, Replay};
+use display::*;
 
-use player::Player;
+use solitaire::player::Player;
+use solitaire::session::*;
+#[cfg(feature = "tui")]
 use ncurses as term;
 
-static COLOR_RED: i16 = 16;
-static COLOR_BLK: i16 = 15;
-static COLOR_BG: i16 = 17;
-static PAIR_RED: i16 = 1;
-static PAIR_BLK: i16 = 2;
-
-static delay_ms: u64 = 0;
-static test_secs: u64 = 60 * 15;
+#[allow(dead_code)]
+static DELAY_MS: u64 = 0;
+static TEST_SECS: u64 = 60 * 15;
 
 
 fn main() {
 
-    let silent = profile_silent();
+    // `solitaire play` is a game for a human and `solitaire replay FILE` checks a replay, anything
+    // else runs the profiles. With --open new deals are played with every card showing
+    let args: Vec<String> = env::args().collect();
+    let rules = Rules { open: args.iter().any(|a| a == "--open"), ..Rules::default() };
+    match args.get(1).map(|a| a.as_str()) {
+        #[cfg(feature = "tui")]
+        Some("play") => {
+            tui::play_human(rules);
+            return;
+        },
+        Some("replay") => {
+            check_replay(args.get(2));
+            return;
+        },
+        _ => (),
+    }
+
+    let silent = profile_silent(rules);
     let silent_end = time::Instant::now();
-    let game = profile_stats_game();
+    let game = profile_stats_game(rules);
     let game_end = time::Instant::now();
-    let mv = profile_stats_move();
+    let mv = profile_stats_move(rules);
     let mv_end = time::Instant::now();
-    let full = profile_full_print();
-    let full_end = time::Instant::now();
 
-    print_stats_curses(&silent.game, silent_end);
-    print_stats_curses(&game.game, game_end);
-    print_stats_curses(&mv.game, mv_end);
-    print_stats_curses(&full.game, full_end);
-    term::refresh();
-    term::getch();
-    
-}
+    #[cfg(feature = "tui")]
+    {
+        let full = profile_full_print(rules);
+        let full_end = time::Instant::now();
 
-fn profile_silent() -> Player {
-    let deck = deck();
-    let mut deck = shuffle(&deck);
-    let mut game = game_init();
+        tui::print_stats_curses(&silent, silent_end);
+        tui::print_stats_curses(&game, game_end);
+        tui::print_stats_curses(&mv, mv_end);
+        tui::print_stats_curses(&full, full_end);
+        term::refresh();
+        term::getch();
+    }
 
-    deal(&mut game, &mut deck);
+    #[cfg(not(feature = "tui"))]
+    {
+        print_summary(&silent, silent_end);
+        print_summary(&game, game_end);
+        print_summary(&mv, mv_end);
+    }
+}
+
+fn profile_silent(rules: Rules) -> Session {
+    let mut game = game_init_with_rules(rules);
+    game_restart(&mut game);
 
     let mut player = player::create_player(game);
+    let mut session = session_init();
 
-    while player.game.started.elapsed() <=  std::time::Duration::from_secs(test_secs) {
-        if game_won(&mut player.game) {
-            game_restart(&mut player.game);
-            player::player_reset(&mut player);
+    while session.started.elapsed() <=  std::time::Duration::from_secs(TEST_SECS) {
+        if deal_over(&mut player.game) {
+            next_deal(&mut player, &mut session);
         } else {
-            if !player::play_one_move(&mut player) {
-                game_restart(&mut player.game);
-                player::player_reset(&mut player);
+            if !play_move(&mut player) {
+                next_deal(&mut player, &mut session);
             }
         }
     }
-    print_stats(&player.game);
-    println!("");
-    player
+    print_stats(&session);
+    println!();
+    session
 }
 
-fn profile_stats_game() -> Player {
-    let deck = deck();
-    let mut deck = shuffle(&deck);
-    let mut game = game_init();
-
-    deal(&mut game, &mut deck);
+fn profile_stats_game(rules: Rules) -> Session {
+    let mut game = game_init_with_rules(rules);
+    game_restart(&mut game);
 
     let mut player = player::create_player(game);
+    let mut session = session_init();
 
-    while player.game.started.elapsed() < std::time::Duration::from_secs(test_secs) {
-        if game_won(&mut player.game) {
-            game_restart(&mut player.game);
-            player::player_reset(&mut player);
+    while session.started.elapsed() < std::time::Duration::from_secs(TEST_SECS) {
+        if deal_over(&mut player.game) {
+            next_deal(&mut player, &mut session);
         } else {
-            if !player::play_one_move(&mut player) {
-                game_restart(&mut player.game);
-                player::player_reset(&mut player);
-                print_stats(&player.game);
+            if !play_move(&mut player) {
+                next_deal(&mut player, &mut session);
+                print_stats(&session);
             }
         }
     }
-    println!("");
-    player
+    println!();
+    session
 }
 
-fn profile_stats_move() -> Player {
-    let deck = deck();
-    let mut deck = shuffle(&deck);
-    let mut game = game_init();
-
-    deal(&mut game, &mut deck);
+fn profile_stats_move(rules: Rules) -> Session {
+    let mut game = game_init_with_rules(rules);
+    game_restart(&mut game);
 
     let mut player = player::create_player(game);
+    let mut session = session_init();
 
-    while player.game.started.elapsed() < std::time::Duration::from_secs(test_secs) {
-        if game_won(&mut player.game) {
-            game_restart(&mut player.game);
-            player::player_reset(&mut player);
+    while session.started.elapsed() < std::time::Duration::from_secs(TEST_SECS) {
+        if deal_over(&mut player.game) {
+            next_deal(&mut player, &mut session);
         } else {
-            if !player::play_one_move(&mut player) {
-                game_restart(&mut player.game);
-                player::player_reset(&mut player);
+            if !play_move(&mut player) {
+                next_deal(&mut player, &mut session);
             }
         }
-        print_stats(&player.game);
+        print_stats(&session);
     }
-    println!("");
-    player
+    println!();
+    session
 }
 
-fn profile_full_print() -> Player{
-    term::initscr();
-    term::start_color();
-    term::init_color(COLOR_RED, 219*4, 51*4, 47*4);
-    term::init_color(COLOR_BLK, 256*4, 256*4, 256*4);
-    term::init_color(COLOR_BG, 0, 0, 0);
-    term::init_pair(PAIR_RED, COLOR_RED, COLOR_BG);
-    term::init_pair(PAIR_BLK, COLOR_BLK, COLOR_BG);
+#[cfg(feature = "tui")]
+fn profile_full_print(rules: Rules) -> Session {
+    tui::init_curses();
 
-    let deck = deck();
-    let mut deck = shuffle(&deck);
-    let mut game = game_init();
-
-    deal(&mut game, &mut deck);
+    let mut game = game_init_with_rules(rules);
+    game_restart(&mut game);
 
     let mut player = player::create_player(game);
+    let mut session = session_init();
 
-    while player.game.started.elapsed() < std::time::Duration::from_secs(test_secs) {
-        if game_won(&mut player.game) {
-            game_restart(&mut player.game);
-            player::player_reset(&mut player);
+    while session.started.elapsed() < std::time::Duration::from_secs(TEST_SECS) {
+        if deal_over(&mut player.game) {
+            next_deal(&mut player, &mut session);
         } else {
-            if !player::play_one_move(&mut player) {
-                game_restart(&mut player.game);
-                player::player_reset(&mut player);
+            if !play_move(&mut player) {
+                next_deal(&mut player, &mut session);
             }
         }
-        print_game(&player.game);
+        tui::print_game(&player.game, &session);
     }
-    player
+    session
 }
 
-#[allow(dead_code)]
-fn play_human() {
-
-    let deck = deck();
-    let mut deck = shuffle(&deck);
-    let mut game = game_init();
-
-    deal(&mut game, &mut deck);
-
-    let mut ch;
-    let mut src_pile;
-    let mut src_depth;
-    let mut dest_pile;
-    loop {
-        term::clear();
-        if game_won(&mut game) {
-            term::printw("=======================================\nWIN\n=======================================");
-            game_restart(&mut game);
-        }
-        print_game(&game);
-        term::refresh();
-        let mut valid = false;
-        while !valid {
-            valid = false;
-            loop {
-                term::printw("\nSrc pile?: ");
-                term::refresh();
-                ch = term::getch();
-                src_pile = src_index_from_char(ch);
-
-                if src_pile != 99 { break };
+// Plays the player's next move. Should the player panic, the deal so far is written out as a
+// replay before the panic carries on, so that the crash can be reproduced. The runners deal every
+// game by number, the first included: a deal without one would be recorded by undoing the whole
+// game, which fails the same debug checks again if those were what panicked
+fn play_move(player: &mut Player) -> bool {
+    match panic::catch_unwind(panic::AssertUnwindSafe(|| player::play_one_move(player))) {
+        Ok(played) => played,
+        Err(e) => {
+            let path = match player.game.seed {
+                Some(seed) => format!("crash-{}.replay", seed),
+                None => "crash.replay".to_string(),
+            };
+            match fs::write(&path, replay::record(&player.game).to_string()) {
+                Ok(()) => eprintln!("\nReplay of the crashed deal written to {}", path),
+                Err(err) => eprintln!("\nCould not write {}: {}", path, err),
             }
-
-            if src_pile == 12 {
-                draw(&mut game);
-                valid = true;
-            } else if src_pile == 13 {
-                game_restart(&mut game);
-            } else {
-
-                loop {
-                    term::printw("\nsrc depth?: ");
-                    term::refresh();
-                    ch = term::getch();
-                    src_depth = depth_from_char(ch);
-                    if src_depth != 99 { break };
-                }
-
-                loop {
-                    term::printw("\ndest_pile?: ");
-                    term::refresh();
-                    ch = term::getch();
-                    dest_pile = dest_index_from_char(ch);
-                    if dest_pile != 99 { break };
-                }
-
-                term::printw(&format!("\n{}, {}, {}\n", src_pile, src_depth, dest_pile));
-                term::refresh();
-                valid = make_move(&mut game, src_pile, src_depth, dest_pile);
-            }
-        }
+            panic::resume_unwind(e)
+        },
     }
 }
 
-// Takes a character from getch() and returns the pile index
-// zero through 6 refer to the piles, h s d c refers to the foundations indices 7 through 10, 
-// q draws from the hand and w draws from the side deck to the hand
-// r restarts the game
-fn src_index_from_char(ch: i32) -> usize {
-    match char::from_u32(ch as u32).expect("Invalid char") {
-        '1' => 0,
-        '2' => 1,
-        '3' => 2,
-        '4' => 3,
-        '5' => 4,
-        '6' => 5,
-        '7' => 6,
-        'q' => 11,
-        'w' => 12,
-        'r' => 13,
-        _ => 99,
+// Plays back the replay in the named file, exiting with an error if it no longer plays the same
+fn check_replay(path: Option<&String>) {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: solitaire replay FILE");
+            process::exit(2);
+        },
+    };
+    let result = fs::read_to_string(path).map_err(|e| e.to_string())
+        .and_then(|text| text.parse::<Replay>().map_err(|e| e.to_string()))
+        .and_then(|r| replay::replay(&r).map_err(|e| e.to_string()));
+    match result {
+        Ok(game) => println!("{}: {} moves replayed, {}", path, game.moves, replay::ending(&game)),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        },
     }
 }
 
-fn dest_index_from_char(ch: i32) -> usize {
-    match char::from_u32(ch as u32).expect("Invalid char") {
-        '1' => 0,
-        '2' => 1,
-        '3' => 2,
-        '4' => 3,
-        '5' => 4,
-        '6' => 5,
-        '7' => 6,
-        'h' => 7,
-        's' => 8,
-        'd' => 9,
-        'c' => 10,
-        _ => 99,
-    }
+// Whether the deal is over: won, sweeping the cards up first if that is all that is left to do,
+// or lost. Whether it is lost is only looked into as the side deck is turned over, once a pass
+fn deal_over(game: &mut Game) -> bool {
+    autocomplete(game);
+    game_won(game) || (game.history.last().map(|step| step.mv) == Some(Move::Recycle)
+                       && game_status(game) == GameStatus::Lost)
 }
 
-fn depth_from_char(ch: i32) -> usize {
-    match char::from_u32(ch as u32).expect("Invalid char") {
-        '1' => 1,
-        '2' => 2,
-        '3' => 3,
-        '4' => 4,
-        '5' => 5,
-        '6' => 6,
-        '7' => 7,
-        '8' => 8,
-        '9' => 9,
-        _ =>  99,
-    }
+// Records the deal the player has finished with and deals them the next one
+fn next_deal(player: &mut Player, session: &mut Session) {
+    record_game(session, &game_result(&player.game));
+    game_restart(&mut player.game);
+    player::player_reset(player);
 }
 
+#[allow(dead_code)]
 fn delay() {
-    wait_millis(delay_ms);
+    wait_millis(DELAY_MS);
 }
 
+#[allow(dead_code)]
 fn wait_millis(millis: u64) {
     let dur = time::Duration::from_millis(millis);
     sleep(dur);
@@ -0,0 +1,274 @@
+//! A compact, fixed size form of a position, small enough to keep millions of them in memory or
+//! on disk and usable directly as a map key.
+//!
+//! The 52 bytes hold, as a stream of bits from the low bit of the first byte:
+//!
+//! - for each pile, its length in 5 bits and how many of its cards are face down in 3
+//! - for each foundation, its suit in 2 bits and how many cards it holds in 4
+//! - the number of cards in the hand in 5 bits
+//! - the passes made through the side deck in 16 bits, saturating
+//! - each card not on a foundation in 6 bits, as Card::index: the piles from first to last and
+//!   each from the bottom up, then the hand and the side deck from first to last
+//!
+//! Only the position is kept. A decoded game has the rules, scoring and counters of game_init
+//! and no history. The rules are dropped along with the rest: a game played with limited passes
+//! comes back with unlimited ones, its recycles kept but left out of its hash. Set the rules it
+//! was played under and call zobrist::rehash to get back the hash it had.
+use std::fmt;
+use std::error;
+
+use game::{Game, Card, Suit, Rank, InvariantViolation, game_init};
+use zobrist::rehash;
+
+/// A position packed by encode
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Packed(pub [u8; 52]);
+
+/// Why bytes could not be unpacked into a game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A card number above 51
+    BadCard(u8),
+    /// A foundation holding more than 13 cards
+    BadFoundation(usize),
+    /// The bytes name a board the rules could never produce
+    Invalid(InvariantViolation),
+    /// The board is sound but encode would pack it differently, with bits set that it leaves
+    /// clear. Refused so that each position has the one packed form
+    NotCanonical,
+}
+
+impl fmt::Debug for Packed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::BadCard(n) => write!(f, "No card numbered {}", n),
+            DecodeError::BadFoundation(i) => write!(f, "Foundation {} holds more than 13 cards", i + 1),
+            DecodeError::Invalid(e) => write!(f, "Not a real position: {}", e),
+            DecodeError::NotCanonical => write!(f, "Not packed the way encode packs it"),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+/// Packs the position of a game. The game must be one the rules produced, see Game::validate
+pub fn encode(game: &Game) -> Packed {
+    let mut bits = Bits { bytes: [0; 52], at: 0 };
+    for pile in &game.piles {
+        bits.write(pile.len() as u32, 5);
+        bits.write(pile.iter().take_while(|c| !c.up).count() as u32, 3);
+    }
+    for found in &game.foundations {
+        bits.write(found.first().map_or(0, |c| c.suit as u32), 2);
+        bits.write(found.len() as u32, 4);
+    }
+    bits.write(game.hand.len() as u32, 5);
+    bits.write(game.recycles.min(0xFFFF) as u32, 16);
+
+    let cards = game.piles.iter().chain(Some(&game.hand)).chain(Some(&game.side_deck));
+    for card in cards.flat_map(|cards| cards.iter()) {
+        bits.write(card.index() as u32, 6);
+    }
+    Packed(bits.bytes)
+}
+
+/// Unpacks a position made by encode, checking that it is one the rules could produce and that
+/// encode would give back the same bytes
+pub fn decode(packed: &Packed) -> Result<Game, DecodeError> {
+    let mut bits = Bits { bytes: packed.0, at: 0 };
+    let mut game = game_init();
+
+    let mut lengths = [(0, 0); 7];
+    for length in lengths.iter_mut() {
+        *length = (bits.read(5) as usize, bits.read(3) as usize);
+    }
+    let mut on_foundations = 0;
+    for (i, found) in game.foundations.iter_mut().enumerate() {
+        let suit = Suit::ALL[bits.read(2) as usize];
+        let height = bits.read(4) as usize;
+        if height > 13 {
+            return Err(DecodeError::BadFoundation(i));
+        }
+        *found = Rank::ALL[..height].iter().map(|&rank| Card { suit, rank, up: true }).collect();
+        on_foundations += height;
+    }
+    let hand = bits.read(5) as usize;
+    game.recycles = bits.read(16) as usize;
+
+    let mut next_card = |up: bool| {
+        let n = bits.read(6) as u8;
+        if n < 52 {
+            Ok(Card { suit: Suit::ALL[n as usize / 13], rank: Rank::ALL[n as usize % 13], up })
+        } else {
+            Err(DecodeError::BadCard(n))
+        }
+    };
+    let mut placed = 0;
+    for (pile, &(len, down)) in game.piles.iter_mut().zip(lengths.iter()) {
+        for i in 0..len {
+            pile.push(next_card(i >= down)?);
+        }
+        placed += len;
+    }
+    for _ in 0..hand {
+        game.hand.push(next_card(true)?);
+    }
+    placed += hand;
+    for _ in placed..(52 - on_foundations).max(placed) {
+        game.side_deck.push(next_card(false)?);
+    }
+
+    // The hash is only worked out once the cards are known to be sound
+    match game.validate() {
+        Ok(()) | Err(InvariantViolation::StaleHash) => (),
+        Err(e) => return Err(DecodeError::Invalid(e)),
+    }
+    if encode(&game) != *packed {
+        return Err(DecodeError::NotCanonical);
+    }
+    rehash(&mut game);
+    Ok(game)
+}
+
+// Reads and writes a stream of bits, low bit first
+struct Bits {
+    bytes: [u8; 52],
+    at: usize,
+}
+
+impl Bits {
+    fn write(&mut self, value: u32, width: usize) {
+        for i in 0..width {
+            if value >> i & 1 == 1 {
+                self.bytes[self.at / 8] |= 1 << (self.at % 8);
+            }
+            self.at += 1;
+        }
+    }
+
+    // Reads as zeroes past the end, so that bad lengths are caught by validation instead
+    fn read(&mut self, width: usize) -> u32 {
+        let mut value = 0;
+        for i in 0..width {
+            if self.at < 52 * 8 && self.bytes[self.at / 8] >> (self.at % 8) & 1 == 1 {
+                value |= 1 << i;
+            }
+            self.at += 1;
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::{Rules, Move, SplitMix64, game_init_with_rules, game_restart_with_seed, make_move};
+
+    // The same board, ignoring everything but the cards and the passes made
+    fn same_position(a: &Game, b: &Game) -> bool {
+        a.piles == b.piles && a.foundations == b.foundations && a.hand == b.hand
+            && a.side_deck == b.side_deck && a.recycles == b.recycles
+    }
+
+    #[test]
+    fn positions_come_back_unchanged() {
+        let mut rng = SplitMix64::new(24);
+        for seed in 0..60 {
+            let rules = Rules { draw_count: 1 + seed as usize % 3, max_passes: Some(4), ..Rules::default() };
+            let mut game = game_init_with_rules(rules);
+            game_restart_with_seed(&mut game, seed);
+            for _ in 0..300 {
+                let packed = encode(&game);
+                let decoded = decode(&packed).unwrap();
+                assert!(same_position(&game, &decoded));
+                assert_eq!(encode(&decoded), packed);
+
+                let mut hashed = decoded.clone();
+                hashed.rules = game.rules;
+                rehash(&mut hashed);
+                assert_eq!(hashed.hash, game.hash);
+
+                // Random play, leaning towards the moves that are not draws
+                let moves = game.legal_moves();
+                let others: Vec<Move> = moves.iter().cloned().filter(|&mv| mv != Move::Draw).collect();
+                let choice = if !others.is_empty() && rng.below(4) != 0 { others } else { moves };
+                if choice.is_empty() {
+                    break;
+                }
+                let mv = choice[rng.below(choice.len() as u64) as usize];
+                make_move(&mut game, mv).unwrap();
+            }
+        }
+    }
+
+    fn dealt() -> Packed {
+        let mut game = game_init_with_rules(Rules::default());
+        game_restart_with_seed(&mut game, 1);
+        encode(&game)
+    }
+
+    // Overwrites width bits from the given bit onwards
+    fn set_bits(packed: &mut Packed, at: usize, width: usize, value: u32) {
+        for i in 0..width {
+            let (byte, bit) = ((at + i) / 8, (at + i) % 8);
+            packed.0[byte] = packed.0[byte] & !(1 << bit) | ((value >> i & 1) as u8) << bit;
+        }
+    }
+
+    // Where the cards start: 7 piles of 8 bits, 4 foundations of 6, 5 for the hand, 16 for passes
+    const CARDS: usize = 7 * 8 + 4 * 6 + 5 + 16;
+
+    #[test]
+    fn corrupt_bytes_are_refused() {
+        let mut bad_card = dealt();
+        set_bits(&mut bad_card, CARDS, 6, 60);
+        assert_eq!(decode(&bad_card).unwrap_err(), DecodeError::BadCard(60));
+
+        let mut bad_foundation = dealt();
+        set_bits(&mut bad_foundation, 7 * 8 + 6 + 2, 4, 14);
+        assert_eq!(decode(&bad_foundation).unwrap_err(), DecodeError::BadFoundation(1));
+
+        let mut duplicate = dealt();
+        set_bits(&mut duplicate, CARDS, 6, 0);
+        set_bits(&mut duplicate, CARDS + 6, 6, 0);
+        assert!(matches!(decode(&duplicate), Err(DecodeError::Invalid(InvariantViolation::DuplicateCard(_)))));
+
+        let mut face_down_top = dealt();
+        set_bits(&mut face_down_top, 8 + 5, 3, 2);
+        assert_eq!(decode(&face_down_top).unwrap_err(),
+                   DecodeError::Invalid(InvariantViolation::TopCardFaceDown { pile: 1 }));
+
+        let mut trailing = dealt();
+        trailing.0[51] |= 0x80;
+        assert_eq!(decode(&trailing).unwrap_err(), DecodeError::NotCanonical);
+
+        let mut suited_empty = dealt();
+        set_bits(&mut suited_empty, 7 * 8, 2, 3);
+        assert_eq!(decode(&suited_empty).unwrap_err(), DecodeError::NotCanonical);
+
+        assert!(decode(&Packed([0; 52])).is_err());
+        assert!(decode(&Packed([0xFF; 52])).is_err());
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = SplitMix64::new(52);
+        let mut packed = dealt();
+        for _ in 0..2000 {
+            let bit = rng.below(52 * 8) as usize;
+            packed.0[bit / 8] ^= 1 << (bit % 8);
+            if let Ok(game) = decode(&packed) {
+                assert_eq!(encode(&game), packed);
+            }
+        }
+    }
+}
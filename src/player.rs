@@ -1,210 +1,125 @@
-use super::game;
+//! A simple automatic player. It plays each position by a fixed order of preference and is used
+//! to run large numbers of deals for statistics.
 use game::{Game, Card};
-use game::{make_move, draw};
-
-use ncurses as term;
+use game::{make_move, draw, safe_to_foundation, Move};
+use game::{suit_alternates, number_match_asc, number_match_desc};
 
+/// Plays a Game on its own, one move at a time with play_one_move
 pub struct Player {
     pub game: Game,
-    pub found_level: u8, // Maximum safe level to play to foundation
     played_this_round: bool, // Tracks if we have made any moves since the last reset of the side_deck
-    restrained: bool, // Determines if we stick to the limit imposed by found_level
+    restrained: bool, // Determines if we only play cards that are safe_to_foundation
 }
 
 pub fn create_player(game: Game) -> Player {
     Player {
-        game: game,
-        found_level: 2,
+        game,
         played_this_round: false,
         restrained: true,
     }
 }
 
+/// Forget everything learnt about the last deal. Call after dealing again
 pub fn player_reset(player: &mut Player) {
-    player.found_level = 2;
     player.played_this_round = false;
     player.restrained = true;
 }
 
+/// Plays the player's preferred move, returning false once it has nothing left worth playing
 pub fn play_one_move(player: &mut Player) -> bool {
+    let moves = player.game.legal_moves();
 
     // We want to play the first move we find in a certain heirarchy of possible move types.
-    if play_hand_found(player)   { return true };
-    if play_pile_found(player)   { return true };
-    if play_pile_pile(player)    { return true };
-    if play_hand_pile(player)    { return true };
-    if play_reveal_found(player) { return true };
-    if play_draw_hand(player)    { return true };
+    if play_hand_found(player, &moves)   { return true };
+    if play_pile_found(player, &moves)   { return true };
+    if play_pile_pile(player, &moves)    { return true };
+    if play_hand_pile(player, &moves)    { return true };
+    if play_reveal_found(player, &moves) { return true };
+    if play_found_pile(player, &moves)   { return true };
+    if play_draw_hand(player)            { return true };
 
     false
  
 }
 
-fn play_hand_found(player: &mut Player) -> bool {
-    let mut game = &mut player.game;
-
-    if game.hand.is_empty() {
-        return false;
-    }
-
-    let top_card = game.hand.last().unwrap().clone();
-
-    if player.restrained && top_card.number > player.found_level {
-        return false;
+// Plays a move taken from legal_moves, which the engine has already agreed to
+fn play(player: &mut Player, mv: Move) {
+    if let Err(e) = make_move(&mut player.game, mv) {
+        panic!("ERROR playing {}: {}", mv, e);
     }
-
-    let target_suit = match top_card.suit {
-        'H' => 0,
-        'S' => 1,
-        'D' => 2,
-        'C' => 3,
-        _ => 4,
-    };
-
-    match game.foundations[target_suit].last() {
-        None =>    if top_card.number != 1 { return false; }
-        Some(c) => { if c.number + 1 != top_card.number { return false; }},
-    };
-
-    // If we have reached here, we should be able to make the move
-    if !make_move(&mut game, 11, 1, target_suit + 7) {
-        panic!("ERROR in play_hand_found");
-    };
     player.played_this_round = true;
-
-    // Update found_level if necessary
-    let mut update = true;
-    for found in &game.foundations {
-        match found.last() {
-            None => update = false,
-            Some(c) => if c.number + 1 < player.found_level { update = false },
-        }
-    }
-    if update {
-        player.found_level += 1;
-    }
-    true
 }
 
-fn play_pile_found(player: &mut Player) -> bool {
-    let mut game = &mut player.game;
-
-    for (i, pile) in game.piles.clone().iter().enumerate() {
-        
-        if pile.is_empty() {
-            continue;
-        }
-
-        let lowest_card = pile.last().unwrap().clone();
-        
-        if player.restrained && lowest_card.number > player.found_level {
-            continue;
-        }
-
-        let target_suit = match lowest_card.suit {
-            'H' => 0,
-            'S' => 1,
-            'D' => 2,
-            'C' => 3,
-            _ => 4,
-        };
-
-        match game.foundations[target_suit].last() {
-            None =>    if lowest_card.number != 1 { continue; },
-            Some(c) => if c.number + 1 != lowest_card.number { continue; },
-        }
+// Whether the card may go to the foundations yet. Until a pass through the side deck finds
+// nothing else to play, only cards the engine's auto play would also move are played up
+fn may_go_up(player: &Player, card: &Card) -> bool {
+    !player.restrained || safe_to_foundation(&player.game, card)
+}
 
-        // We should be able to make a move now
-        if !make_move(&mut game, i, 1, target_suit + 7) {
-            panic!("ERROR in play_pile_found");
-        }
-        player.played_this_round = true;
+fn play_hand_found(player: &mut Player, moves: &[Move]) -> bool {
+    let mv = moves.iter().cloned().find(|mv| match *mv {
+        Move::WasteToFoundation { .. } => may_go_up(player, player.game.hand.last().unwrap()),
+        _ => false,
+    });
 
-        // Update found_level if necessary
-        let mut update = true;
-        for found in &game.foundations {
-            match found.last() {
-                None => update = false,
-                Some(c) => if c.number + 1 < player.found_level { update = false },
-            }
-        }
-        if update {
-            player.found_level += 1;
-        }
-        return true;
+    match mv {
+        None => false,
+        Some(mv) => {
+            play(player, mv);
+            true
+        },
     }
-    false 
 }
 
-fn play_pile_pile(player: &mut Player) -> bool {
-    let mut game = &mut player.game;
-
-    for (i, pile) in game.piles.clone().iter().enumerate() {
-
-        if pile.is_empty() {
-            continue;
-        }
-
-        let (highest_card, depth) = get_highest_card(pile);
-
-        // Look at each pile other than this one for a spot to put it
-        for (j, target) in game.piles.clone().iter().enumerate() {
-
-            let lowest_card = target.last();
-
-            if target.is_empty() {
-                // Moving to empty pile, we dont want non kings, or kings that are already on the
-                // base
-                if highest_card.number != 13 || depth == pile.len() {
-                    continue;
-                }
-            } else if !game::suit_alternates(lowest_card.unwrap(), &highest_card) 
-                || !game::number_match_desc(lowest_card, &highest_card) {
-                continue;
-            }
+fn play_pile_found(player: &mut Player, moves: &[Move]) -> bool {
+    let mv = moves.iter().cloned().find(|mv| match *mv {
+        Move::TableauToFoundation { src, .. } => may_go_up(player, player.game.piles[src].last().unwrap()),
+        _ => false,
+    });
 
-            // We should be able to make a move
-            if !make_move(&mut game, i, depth, j) {
-                panic!("ERROR in play_pile_pile");
-            }
-            player.played_this_round = true;
-            return true;
-        }
+    match mv {
+        None => false,
+        Some(mv) => {
+            play(player, mv);
+            true
+        },
     }
-    false
-
 }
 
-fn play_hand_pile(player: &mut Player) -> bool {
-    let mut game = &mut player.game;
-
-    if game.hand.is_empty() {
-        return false;
+fn play_pile_pile(player: &mut Player, moves: &[Move]) -> bool {
+    let piles = &player.game.piles;
+
+    // Only whole face up runs are moved. Moving to empty pile, we dont want kings that are already
+    // on the base. When the cards are open, a move uncovering a card that can go straight to the
+    // foundations comes first
+    let candidates: Vec<Move> = moves.iter().cloned().filter(|mv| match *mv {
+        Move::TableauToTableau { src, depth, dest } => {
+            depth == face_up(&piles[src]) && !(piles[dest].is_empty() && depth == piles[src].len())
+        },
+        _ => false,
+    }).collect();
+    let mv = candidates.iter().cloned().find(|mv| uncovers_found(player, mv))
+        .or_else(|| candidates.first().cloned());
+
+    match mv {
+        None => false,
+        Some(mv) => {
+            play(player, mv);
+            true
+        },
     }
+}
 
-    let card = game.hand.last().unwrap().clone();
-
-    for (j, target) in game.piles.clone().iter().enumerate() {
-
-        let lowest_card = target.last();
-
-        if target.is_empty() {
-            if card.number != 13 {
-                continue;
-            }
-        } else if !game::suit_alternates(lowest_card.unwrap(), &card)
-            || !game::number_match_desc(lowest_card, &card) {
-                continue;
-        }
+fn play_hand_pile(player: &mut Player, moves: &[Move]) -> bool {
+    let mv = moves.iter().cloned().find(|mv| matches!(*mv, Move::WasteToTableau { .. }));
 
-        // We should be able to make a move
-        if !make_move(&mut game, 11, 1, j) {
-            panic!("ERROR in play_hand_pile");
-        }
-        player.played_this_round = true;
-        return true;
+    match mv {
+        None => false,
+        Some(mv) => {
+            play(player, mv);
+            true
+        },
     }
-    false
 }
 
 fn play_draw_hand(player: &mut Player) -> bool {
@@ -227,86 +142,96 @@ fn play_draw_hand(player: &mut Player) -> bool {
     }
 }
 
-fn play_reveal_found(player: &mut Player) -> bool {
-    let mut game = &mut player.game;
-
+fn play_reveal_found(player: &mut Player, moves: &[Move]) -> bool {
     // We are looking for foundation cards hidden in stacks that we can reveal.
     // Find which card each foundation needs next, if that card is present in a stack, look for a
     // place to move the cards hiding it
-    for (suit, found) in game.foundations.clone().iter().enumerate() {
-        if found.is_empty() { continue; }
-        let target = target_card(suit, found);
-        
-        if player.restrained && target.number > player.found_level {
-            continue;
-        }
-
-        for (i, pile) in game.piles.clone().iter().enumerate() {
-            for (depth, card) in pile.iter().enumerate() {
-                let depth = pile.len() - depth - 1;
-                if card_match_exact(&card, &target) && depth != 0 {
-                    for (j, dest_pile) in game.piles.clone().iter().enumerate() {
-                        if !dest_pile.is_empty() {
-                            let bottom_card = dest_pile.last();
-                            if bottom_card.is_none() {
-                                continue;
-                            }
-                            let bottom_card = bottom_card.unwrap();
-                            if card_match_functional(&bottom_card, &card) {
-                                // We can make a move
-                                if !make_move(&mut game, i, depth, j){
-                                    println!("Error in play_reveal_found");
-                                    panic!();
-                                }
-                                player.played_this_round = true;
-                                return true;
-                            }
-                        }
-                    }
-                }
-            }
+    let targets: Vec<Card> = player.game.foundations.iter()
+        .filter(|found| !found.is_empty())
+        .filter_map(|found| target_card(found))
+        .filter(|target| may_go_up(player, target))
+        .collect();
+
+    let piles = &player.game.piles;
+    for target in targets {
+        let mv = moves.iter().cloned().find(|mv| match *mv {
+            Move::TableauToTableau { src, depth, dest } => {
+                let pile = &piles[src];
+                depth < pile.len() && card_match_exact(&player.game, &pile[pile.len() - depth - 1], &target)
+                    && !piles[dest].is_empty()
+            },
+            _ => false,
+        });
+
+        if let Some(mv) = mv {
+            play(player, mv);
+            return true;
         }
     }
     false
 }
 
-fn target_card(suit: usize, pile: &[Card]) -> Card {
-    let mut card = pile.last().unwrap();
-    let mut res = card.clone();
-    res.number += 1;
-    res
-}
+fn play_found_pile(player: &mut Player, moves: &[Move]) -> bool {
+    // Taking a card back down from a foundation is only worth it if something can then be played
+    // onto it: the top of the hand, or a run whose removal reveals a card. Both moves are made here
+    // so that the card does not go straight back up on the next turn
+    for mv in moves {
+        let (src, dest) = match *mv {
+            Move::FoundationToTableau { src, dest } => (src, dest),
+            _ => continue,
+        };
+        let game = &player.game;
+        let card = *game.foundations[src].last().unwrap();
+        let fits = |c: &Card| suit_alternates(c, &card) && number_match_desc(Some(&card), c);
 
-fn card_match_exact(a: &Card, b: &Card) -> bool {
-    a.up && b.up && a.number == b.number && a.suit == b.suit
-}
+        let follow = if game.hand.last().is_some_and(&fits) {
+            Some(Move::WasteToTableau { dest })
+        } else {
+            game.piles.iter().enumerate().find_map(|(i, pile)| {
+                let depth = face_up(pile);
+                if i != dest && depth > 0 && depth < pile.len() && fits(&pile[pile.len() - depth]) {
+                    Some(Move::TableauToTableau { src: i, depth, dest })
+                } else {
+                    None
+                }
+            })
+        };
 
-fn card_match_functional(a: &Card, b: &Card) -> bool {
-    if a.number == b.number {
-        match a.suit {
-            'H' | 'D' => return b.suit == 'H' || b.suit == 'D',
-            'C' | 'S' => return b.suit == 'C' || b.suit == 'S',
-            _ => return false,
+        if let Some(follow) = follow {
+            play(player, *mv);
+            play(player, follow);
+            return true;
         }
-    } else {
-        return false;
     }
+    false
 }
 
+// The card which would be played next on the given foundation, None once it is complete
+fn target_card(pile: &[Card]) -> Option<Card> {
+    let card = pile.last().unwrap();
+    card.rank.next().map(|rank| Card { rank, ..*card })
+}
 
-fn get_highest_card(pile: &[Card]) -> (Card, usize) {
-    let mut pile = pile.to_owned();
-
-    let mut next = pile.pop();
-    let mut card = game::card(&'D', 0); // Dummy card which will not be used
-    let mut depth = 0;
+fn card_match_exact(game: &Game, a: &Card, b: &Card) -> bool {
+    game.known(a) && game.known(b) && a.rank == b.rank && a.suit == b.suit
+}
 
-    while next.is_some() && next.clone().unwrap().up {
-        card = next.unwrap();
-        next = pile.pop();
-        depth += 1;
+// Whether the pile to pile move turns over a card known to be playable to the foundations
+fn uncovers_found(player: &Player, mv: &Move) -> bool {
+    let game = &player.game;
+    match *mv {
+        Move::TableauToTableau { src, depth, .. } if depth < game.piles[src].len() => {
+            let pile = &game.piles[src];
+            let card = &pile[pile.len() - depth - 1];
+            !card.up && game.known(card) && may_go_up(player, card)
+                && game.foundation_for(card.suit)
+                    .is_some_and(|found| number_match_asc(game.foundations[found].len(), card))
+        },
+        _ => false,
     }
+}
 
-    (card, depth)
+// The number of face up cards at the bottom of the pile
+fn face_up(pile: &[Card]) -> usize {
+    pile.iter().rev().take_while(|c| c.up).count()
 }
-    
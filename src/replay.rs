@@ -0,0 +1,362 @@
+//! Recording a deal and playing it back. A Replay holds the starting layout, the rules and every
+//! move played; replay() plays it again through make_move and checks each move is still legal
+//! and that the deal ends the way it did before.
+//!
+//! As text a replay is one entry per line, blank lines and lines starting with # ignored:
+//!
+//! ```text
+//! seed 1234
+//! draw 3
+//! passes unlimited
+//! empty kings
+//! cards hidden
+//! scoring vegas-cumulative
+//! moves 3>F1 D W>5 2>5x2
+//! moves D D R
+//! end lost -47
+//! ```
+//!
+//! `deck` followed by the 52 cards in the order given to deal may stand in for `seed`. `moves`
+//! lines use the notation of Move's Display and are played in order. `cards open` marks a
+//! Thoughtful deal. `cards` and `end` are optional.
+use std::fmt;
+use std::error;
+use std::str::FromStr;
+
+use game::{Game, Card, Move, MoveError, ParseMoveError, Rules, EmptyColumn};
+use game::{game_init_with_rules, game_restart_with_seed, deal, game_won, make_move, undo, card};
+use scoring::{Scoring, scheme_by_name};
+
+/// Where a replayed deal starts from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Start {
+    /// The deal numbered by this seed, see game_restart_with_seed
+    Seed(u64),
+    /// This deck, as it would be given to deal
+    Deck(Vec<Card>),
+}
+
+/// How a deal stood at the end. The score leaves out any bonus for winning and any charge for
+/// time, which depend on how long the game took
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ending {
+    pub won: bool,
+    pub score: isize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub start: Start,
+    pub rules: Rules,
+    pub scoring: &'static dyn Scoring,
+    pub moves: Vec<Move>,
+    /// The ending to check against, None to only check that the moves are legal
+    pub end: Option<Ending>,
+}
+
+/// Why a replay could not be played back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The move at this index in the list was rejected
+    IllegalMove { index: usize, mv: Move, error: MoveError },
+    /// Every move was played but the deal ended differently
+    WrongEnding { expected: Ending, actual: Ending },
+}
+
+/// Returned when text is not a replay, naming the line at fault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseReplayError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::IllegalMove { index, mv, error } =>
+                write!(f, "Move {} ({}) is illegal: {}", index + 1, mv, error),
+            ReplayError::WrongEnding { expected, actual } =>
+                write!(f, "Expected the deal to end {} but it ended {}", expected, actual),
+        }
+    }
+}
+
+impl error::Error for ReplayError {}
+
+impl fmt::Display for ParseReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for ParseReplayError {}
+
+/// Written as "won 1234" or "lost -52"
+impl fmt::Display for Ending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", if self.won { "won" } else { "lost" }, self.score)
+    }
+}
+
+/// How the game stands now, as an Ending
+pub fn ending(game: &Game) -> Ending {
+    Ending { won: game_won(game), score: game.score }
+}
+
+/// A replay of everything played in the game so far, ending where it stands now
+pub fn record(game: &Game) -> Replay {
+    let start = match game.seed {
+        Some(seed) => Start::Seed(seed),
+        None => Start::Deck(initial_deck(game)),
+    };
+    Replay {
+        start,
+        rules: game.rules,
+        scoring: game.scoring,
+        moves: game.history.iter().map(|step| step.mv).collect(),
+        end: Some(ending(game)),
+    }
+}
+
+/// Deals the replay's layout and plays each of its moves, returning the game at the end
+pub fn replay(replay: &Replay) -> Result<Game, ReplayError> {
+    let mut game = game_init_with_rules(replay.rules);
+    game.scoring = replay.scoring;
+    match replay.start {
+        Start::Seed(seed) => game_restart_with_seed(&mut game, seed),
+        Start::Deck(ref deck) => deal(&mut game, &mut deck.clone()),
+    }
+
+    for (index, &mv) in replay.moves.iter().enumerate() {
+        if let Err(error) = make_move(&mut game, mv) {
+            return Err(ReplayError::IllegalMove { index, mv, error });
+        }
+    }
+
+    if let Some(expected) = replay.end {
+        let actual = ending(&game);
+        if actual != expected {
+            return Err(ReplayError::WrongEnding { expected, actual });
+        }
+    }
+    Ok(game)
+}
+
+// Works out the deck that deal was given, by taking back every move and gathering the layout up
+// in the reverse of the order it was dealt
+fn initial_deck(game: &Game) -> Vec<Card> {
+    let mut start = game.clone();
+    while undo(&mut start).is_some() {}
+
+    let mut dealt = vec![];
+    for (i, pile) in start.piles.iter().enumerate() {
+        dealt.extend_from_slice(&pile[..i + 1]);
+    }
+    start.side_deck.iter().chain(dealt.iter().rev()).map(|c| card(c.suit, c.rank)).collect()
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.start {
+            Start::Seed(seed) => writeln!(f, "seed {}", seed)?,
+            Start::Deck(ref deck) => {
+                let cards: Vec<String> = deck.iter().map(|c| c.to_string()).collect();
+                writeln!(f, "deck {}", cards.join(" "))?;
+            },
+        }
+        writeln!(f, "draw {}", self.rules.draw_count)?;
+        match self.rules.max_passes {
+            None => writeln!(f, "passes unlimited")?,
+            Some(n) => writeln!(f, "passes {}", n)?,
+        }
+        match self.rules.empty_column {
+            EmptyColumn::KingsOnly => writeln!(f, "empty kings")?,
+            EmptyColumn::AnyCard => writeln!(f, "empty any")?,
+        }
+        writeln!(f, "cards {}", if self.rules.open { "open" } else { "hidden" })?;
+        writeln!(f, "scoring {}", self.scoring.name())?;
+        for line in self.moves.chunks(20) {
+            let moves: Vec<String> = line.iter().map(|mv| mv.to_string()).collect();
+            writeln!(f, "moves {}", moves.join(" "))?;
+        }
+        if let Some(end) = self.end {
+            writeln!(f, "end {}", end)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Replay {
+    type Err = ParseReplayError;
+
+    fn from_str(s: &str) -> Result<Replay, ParseReplayError> {
+        let mut start = None;
+        let mut rules = Rules::default();
+        let mut scoring = None;
+        let mut moves = vec![];
+        let mut end = None;
+
+        for (i, line) in s.lines().enumerate() {
+            let err = |message: &str| ParseReplayError { line: i + 1, message: message.to_string() };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap();
+            let values: Vec<&str> = words.collect();
+            let value = || match values.len() {
+                1 => Ok(values[0]),
+                _ => Err(err(&format!("Expected one value after {}", key))),
+            };
+
+            match key {
+                "seed" => start = Some(Start::Seed(value()?.parse().map_err(|_| err("Bad seed"))?)),
+                "deck" => {
+                    let deck = values.iter().map(|c| c.parse()).collect::<Result<Vec<Card>, _>>()
+                        .map_err(|e| err(&e.to_string()))?;
+                    if deck.len() != 52 {
+                        return Err(err("A deck needs 52 cards"));
+                    }
+                    start = Some(Start::Deck(deck));
+                },
+                "draw" => rules.draw_count = match value()?.parse() {
+                    Ok(0) | Err(_) => return Err(err("Bad draw count")),
+                    Ok(n) => n,
+                },
+                "passes" => rules.max_passes = match value()? {
+                    "unlimited" => None,
+                    n => match n.parse() {
+                        Ok(0) | Err(_) => return Err(err("Bad number of passes")),
+                        Ok(n) => Some(n),
+                    },
+                },
+                "empty" => rules.empty_column = match value()? {
+                    "kings" => EmptyColumn::KingsOnly,
+                    "any" => EmptyColumn::AnyCard,
+                    _ => return Err(err("Empty piles take kings or any")),
+                },
+                "cards" => rules.open = match value()? {
+                    "hidden" => false,
+                    "open" => true,
+                    _ => return Err(err("Cards are hidden or open")),
+                },
+                "scoring" => scoring = Some(scheme_by_name(value()?).ok_or_else(|| err("Unknown scoring"))?),
+                "moves" => for mv in &values {
+                    moves.push(mv.parse().map_err(|e: ParseMoveError| err(&e.to_string()))?);
+                },
+                "end" => {
+                    let won = match values.first() {
+                        Some(&"won") => true,
+                        Some(&"lost") => false,
+                        _ => return Err(err("End must be won or lost")),
+                    };
+                    let score = match values.get(1).map(|n| n.parse()) {
+                        Some(Ok(score)) if values.len() == 2 => score,
+                        _ => return Err(err("End needs a score")),
+                    };
+                    end = Some(Ending { won, score });
+                },
+                _ => return Err(err(&format!("Unknown entry {}", key))),
+            }
+        }
+
+        let missing = |what: &str| ParseReplayError { line: s.lines().count(), message: format!("No {} given", what) };
+        Ok(Replay {
+            start: start.ok_or_else(|| missing("seed or deck"))?,
+            rules,
+            scoring: scoring.ok_or_else(|| missing("scoring"))?,
+            moves,
+            end,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::{game_init, shuffle_seeded};
+    use player::{create_player, play_one_move};
+
+    // A deal played by the automatic player until it gives up
+    fn played(game: Game) -> Game {
+        let mut player = create_player(game);
+        let mut n = 0;
+        while n < 1000 && !game_won(&player.game) && play_one_move(&mut player) {
+            n += 1;
+        }
+        player.game
+    }
+
+    #[test]
+    fn recorded_games_play_back_the_same() {
+        let mut wins = 0;
+        for seed in 0..30 {
+            let mut game = game_init();
+            game_restart_with_seed(&mut game, seed);
+            let game = played(game);
+            let text = record(&game).to_string();
+            let back: Replay = text.parse().unwrap();
+            assert_eq!(back.end, Some(ending(&game)));
+            let again = replay(&back).unwrap();
+            assert_eq!(ending(&again), ending(&game));
+            assert_eq!(again.hash, game.hash);
+            wins += game_won(&game) as usize;
+        }
+        assert!(wins > 0);
+    }
+
+    #[test]
+    fn recorded_deck_is_the_one_dealt() {
+        let deck = shuffle_seeded(7);
+        let mut game = game_init();
+        deal(&mut game, &mut deck.clone());
+        let game = played(game);
+        let recorded = record(&game);
+        assert_eq!(recorded.start, Start::Deck(deck));
+        let text = recorded.to_string();
+        assert_eq!(ending(&replay(&text.parse().unwrap()).unwrap()), ending(&game));
+    }
+
+    #[test]
+    fn illegal_moves_are_found() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 5);
+        let mut recorded = record(&played(game));
+        let index = recorded.moves.len() / 2;
+        recorded.moves.insert(index, Move::Recycle);
+        match replay(&recorded) {
+            Err(ReplayError::IllegalMove { index: at, mv: Move::Recycle, error: MoveError::StockNotEmpty }) => {
+                assert_eq!(at, index)
+            },
+            other => panic!("{:?}", other.map(|g| ending(&g))),
+        }
+    }
+
+    #[test]
+    fn wrong_endings_are_found() {
+        let mut game = game_init();
+        game_restart_with_seed(&mut game, 5);
+        let mut recorded = record(&played(game));
+        let expected = Ending { won: true, score: 0 };
+        recorded.end = Some(expected);
+        match replay(&recorded) {
+            Err(ReplayError::WrongEnding { expected: e, .. }) => assert_eq!(e, expected),
+            other => panic!("{:?}", other.map(|g| ending(&g))),
+        }
+    }
+
+    #[test]
+    fn drawing_no_cards_is_refused() {
+        let error = "seed 3\ndraw 0\nscoring vegas\n".parse::<Replay>().unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn no_passes_is_refused() {
+        let error = "seed 3\ndraw 1\npasses 0\nscoring vegas\n".parse::<Replay>().unwrap_err();
+        assert_eq!(error.line, 3);
+        let replay = "seed 3\ndraw 1\npasses 1\nscoring vegas\n".parse::<Replay>().unwrap();
+        assert_eq!(replay.rules.max_passes, Some(1));
+    }
+}
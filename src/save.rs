@@ -0,0 +1,31 @@
+// Saving a game in progress so a human can stop and carry on later. The file is the Game's JSON
+// form, see solitaire::game::Game for what it holds.
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use solitaire::game::Game;
+
+// Where play_human keeps its game: .solitaire-save.json in the home directory, or the current
+// directory if there is no home
+pub fn save_path() -> PathBuf {
+    let dir = env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    dir.join(".solitaire-save.json")
+}
+
+pub fn save_game(game: &Game, path: &Path) -> io::Result<()> {
+    let json = serde_json::to_string(game).map_err(io::Error::from)?;
+    fs::write(path, json)
+}
+
+// Refuses a file whose board could not have come from a real game, rather than letting play
+// carry on from it
+pub fn load_game(path: &Path) -> io::Result<Game> {
+    let json = fs::read_to_string(path)?;
+    let game: Game = serde_json::from_str(&json).map_err(io::Error::from)?;
+    game.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(game)
+}
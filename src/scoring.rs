@@ -0,0 +1,157 @@
+//! Scoring schemes. The engine reports each deal, move and win to the scheme held in Game and
+//! adds whatever it returns to the score. Carrying scores between deals is left to Session.
+use std::fmt;
+use std::time;
+
+use game::{Move, MoveOutcome, Rules};
+
+/// A way of scoring Klondike. Implementations are stateless, the score itself lives in Game
+pub trait Scoring: fmt::Debug + Sync {
+    /// Short name the scheme is saved under, see scheme_by_name
+    fn name(&self) -> &'static str;
+
+    /// Score at the start of a deal
+    fn deal(&self) -> isize;
+
+    /// Change in score caused by a move that has just been made
+    fn on_move(&self, mv: &Move, outcome: &MoveOutcome, rules: &Rules) -> isize;
+
+    /// Bonus for winning a deal that took the given time
+    fn on_win(&self, elapsed: time::Duration) -> isize;
+
+    /// Change in score for a deal that has gone on for the given time. Counted as the score is
+    /// read rather than kept in Game::score, so that the score after each move does not depend on
+    /// how fast it was played. See game::timed_score
+    fn on_time(&self, _elapsed: time::Duration) -> isize {
+        0
+    }
+
+    /// Whether the score carries over from one deal to the next
+    fn cumulative(&self) -> bool {
+        false
+    }
+
+    /// Lowest score allowed, None if the score may go as low as it likes
+    fn floor(&self) -> Option<isize> {
+        None
+    }
+}
+
+/// Windows Standard scoring:
+///   - waste to tableau +5, to foundation +10, tableau to foundation +10
+///   - turning over a tableau card +5
+///   - foundation to tableau -15
+///   - recycling the waste -100 when drawing one, -20 when drawing three
+///   - a time bonus of 700,000 / seconds on winning, for games over 30 seconds
+///   - -2 for every 10 seconds played
+///   - never below zero
+#[derive(Debug, Clone, Copy)]
+pub struct Standard;
+
+/// Vegas scoring: the deck costs 52, each card on the foundations pays 5 and taking one back
+/// down repays it
+#[derive(Debug, Clone, Copy)]
+pub struct Vegas;
+
+/// Vegas scoring carried over from one deal to the next
+#[derive(Debug, Clone, Copy)]
+pub struct VegasCumulative;
+
+impl Scoring for Standard {
+    fn name(&self) -> &'static str {
+        "standard"
+    }
+
+    fn deal(&self) -> isize {
+        0
+    }
+
+    fn on_move(&self, mv: &Move, outcome: &MoveOutcome, rules: &Rules) -> isize {
+        let points = match *mv {
+            Move::WasteToTableau { .. } => 5,
+            Move::WasteToFoundation { .. } | Move::TableauToFoundation { .. } => 10,
+            Move::FoundationToTableau { .. } => -15,
+            Move::Recycle => if rules.draw_count == 1 { -100 } else { -20 },
+            _ => 0,
+        };
+        if outcome.revealed { points + 5 } else { points }
+    }
+
+    fn on_win(&self, elapsed: time::Duration) -> isize {
+        let secs = elapsed.as_secs();
+        if secs > 30 { (700_000 / secs) as isize } else { 0 }
+    }
+
+    fn on_time(&self, elapsed: time::Duration) -> isize {
+        -2 * (elapsed.as_secs() / 10) as isize
+    }
+
+    fn floor(&self) -> Option<isize> {
+        Some(0)
+    }
+}
+
+impl Scoring for Vegas {
+    fn name(&self) -> &'static str {
+        "vegas"
+    }
+
+    fn deal(&self) -> isize {
+        -52
+    }
+
+    fn on_move(&self, mv: &Move, _outcome: &MoveOutcome, _rules: &Rules) -> isize {
+        match *mv {
+            Move::WasteToFoundation { .. } | Move::TableauToFoundation { .. } => 5,
+            Move::FoundationToTableau { .. } => -5,
+            _ => 0,
+        }
+    }
+
+    fn on_win(&self, _elapsed: time::Duration) -> isize {
+        0
+    }
+}
+
+impl Scoring for VegasCumulative {
+    fn name(&self) -> &'static str {
+        "vegas-cumulative"
+    }
+
+    fn deal(&self) -> isize {
+        Vegas.deal()
+    }
+
+    fn on_move(&self, mv: &Move, outcome: &MoveOutcome, rules: &Rules) -> isize {
+        Vegas.on_move(mv, outcome, rules)
+    }
+
+    fn on_win(&self, elapsed: time::Duration) -> isize {
+        Vegas.on_win(elapsed)
+    }
+
+    fn cumulative(&self) -> bool {
+        true
+    }
+}
+
+/// Looks up one of the schemes above by its name
+pub fn scheme_by_name(name: &str) -> Option<&'static dyn Scoring> {
+    let schemes: [&'static dyn Scoring; 3] = [&Standard, &Vegas, &VegasCumulative];
+    schemes.iter().cloned().find(|s| s.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn standard_charges_for_time() {
+        assert_eq!(Standard.on_time(Duration::from_secs(9)), 0);
+        assert_eq!(Standard.on_time(Duration::from_secs(10)), -2);
+        assert_eq!(Standard.on_time(Duration::from_secs(95)), -18);
+        assert_eq!(Standard.on_win(Duration::from_secs(100)), 7000);
+        assert_eq!(Vegas.on_time(Duration::from_secs(600)), 0);
+    }
+}
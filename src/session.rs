@@ -0,0 +1,42 @@
+//! Statistics over a run of deals. A Game only knows about the deal in progress, whoever is
+//! dealing hands each finished one to record_game.
+use std::time;
+
+use game::{Game, GameResult, timed_score};
+
+/// Totals for every deal recorded so far
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub games: usize,
+    pub wins: usize,
+    /// Moves made in finished deals
+    pub moves: usize,
+    /// Running total if the scoring is cumulative, else the last deal's score
+    pub score: isize,
+    pub started: time::Instant,
+}
+
+pub fn session_init() -> Session {
+    Session {
+        games: 0,
+        wins: 0,
+        moves: 0,
+        score: 0,
+        started: time::Instant::now(),
+    }
+}
+
+/// Adds a finished deal to the totals
+pub fn record_game(session: &mut Session, result: &GameResult) {
+    session.games += 1;
+    if result.won {
+        session.wins += 1;
+    }
+    session.moves += result.moves;
+    session.score = if result.cumulative { session.score + result.score } else { result.score };
+}
+
+/// The score to show while a deal is still being played
+pub fn running_score(session: &Session, game: &Game) -> isize {
+    if game.scoring.cumulative() { session.score + timed_score(game) } else { timed_score(game) }
+}
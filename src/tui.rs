@@ -0,0 +1,387 @@
+// The curses front-end: drawing the board and letting a human play. Only built with the tui
+// feature, the engine and the headless runners do without it.
+use std::time;
+use std::char;
+use std::path::Path;
+
+use ncurses as term;
+
+use solitaire::game::*;
+use solitaire::scoring;
+use solitaire::session::*;
+
+use display::Dur;
+use save::{save_path, save_game, load_game};
+
+static COLOR_RED: i16 = 16;
+static COLOR_BLK: i16 = 15;
+static COLOR_BG: i16 = 17;
+static PAIR_RED: i16 = 1;
+static PAIR_BLK: i16 = 2;
+
+// Starts curses and sets up the card colours
+pub fn init_curses() {
+    term::initscr();
+    term::start_color();
+    term::init_color(COLOR_RED, 219*4, 51*4, 47*4);
+    term::init_color(COLOR_BLK, 256*4, 256*4, 256*4);
+    term::init_color(COLOR_BG, 0, 0, 0);
+    term::init_pair(PAIR_RED, COLOR_RED, COLOR_BG);
+    term::init_pair(PAIR_BLK, COLOR_BLK, COLOR_BG);
+}
+
+pub fn print_stats_curses(session: &Session, ended: time::Instant) {
+    let elapsed = Dur { dur: ended.duration_since(session.started) };
+    term::printw(&format!("{} | {} g/s, {} w/s ",
+                          elapsed,
+                          session.games / (elapsed.dur.as_secs() as usize + 1),
+                          session.wins  / (elapsed.dur.as_secs() as usize + 1)));
+    term::printw(&format!("Score: {} | Winrate: {:4.2}% ({}/{}) | Moves: {}      \n\r", 
+                          session.score, 
+                          100.0 *(session.wins as f32 / session.games as f32),
+                          session.wins,
+                          session.games,
+                          session.moves));
+}
+
+
+pub fn print_game(game: &Game, session: &Session) {
+    term::clear();
+    let elapsed = Dur { dur: session.started.elapsed() };
+    term::printw(&format!("{} | {} g/s, {} w/s ",
+                          elapsed,
+                          session.games / (elapsed.dur.as_secs() as usize + 1),
+                          session.wins  / (elapsed.dur.as_secs() as usize + 1)));
+    term::printw(&format!("Score: {} | Winrate: {:4.2}% ({}/{}) | Moves: {}", 
+                          running_score(session, game), 
+                          100.0 *(session.wins as f32 / session.games as f32),
+                          session.wins,
+                          session.games,
+                          session.moves + game.moves));
+    match game.seed {
+        None => term::printw("\n"),
+        Some(seed) => term::printw(&format!(" | Deal #{}\n", seed)),
+    };
+    // Top line of game in parts:
+    // 1. Side deck size
+    term::printw(&format!("({:2})[", game.side_deck.len()));
+    // 2. Hand
+    match game.hand.last() {
+        None => (),
+        Some(c) => set_colour(c),
+    }
+    term::printw(&match game.hand.last() {
+                     None => "   ".to_string(),
+                     Some(c) => card_string(c),
+                 });
+    match game.hand.last() {
+        None => (),
+        Some(c) => clear_colour(c),
+    }
+    term::printw("]  ");
+
+    // 3. Foundations
+    for found in &game.foundations {
+        term::printw("[");
+        match found.last() {
+            None => (),
+            Some(c) => set_colour(c),
+        }
+        term::printw(&match found.last() {
+                         None => "   ".to_string(),
+                         Some(c) => card_string(c),
+                     });
+        match found.last() {
+            None => (),
+            Some(c) => clear_colour(c),
+        }
+        term::printw("]");
+    }
+    term::printw("\n");
+    // In an open deal the side deck follows, next card to be drawn first, twelve to a line
+    if game.rules.open {
+        let stock: Vec<String> = game.side_deck.iter().rev().map(card_string).collect();
+        for line in stock.chunks(12) {
+            term::printw(&format!("Next: {}\n", line.join(" ")));
+        }
+    }
+    term::printw("=============================\n");
+    let mut cards = true;
+    let mut row = 0;
+    while cards {
+        cards = false;
+        for pile in &game.piles {
+            if row < pile.len() {
+                cards = true;
+                set_colour(&pile[row]);
+                term::printw(&format!("{} ", card_str_disp(game, &pile[row])));
+                clear_colour(&pile[row]);
+            } else {
+                term::printw("    ");
+            }
+        }
+        row += 1;
+        term::printw("\n");
+    }
+    term::refresh();
+}
+
+// Face down cards are left uncoloured when the deal is open
+pub fn card_str_disp(game: &Game, card: &Card) -> String {
+    if game.known(card) { card_string(card) }
+    else { " XX".to_string() }
+}
+
+pub fn set_colour(card: &Card) {
+    if card.up {
+       match card.colour() {
+           Colour::Red => {
+               term::attron(term::COLOR_PAIR(PAIR_RED));
+           },
+           Colour::Black => {
+               term::attron(term::COLOR_PAIR(PAIR_BLK));
+           },
+       }
+    }
+}
+
+pub fn clear_colour(card: &Card) {
+    if card.up {
+       match card.colour() {
+           Colour::Red => {
+               term::attroff(term::COLOR_PAIR(PAIR_RED));
+           },
+           Colour::Black => {
+               term::attroff(term::COLOR_PAIR(PAIR_BLK));
+           },
+       }
+    }
+}
+
+// A saved game keeps its own rules, the given ones are for a new deal
+pub fn play_human(rules: Rules) {
+    init_curses();
+
+    let path = save_path();
+    let mut game = match continue_last_game(&path) {
+        Some(game) => game,
+        None => {
+            let mut game = game_init_with_rules(rules);
+            game.scoring = &scoring::Standard;
+            game.auto_play = true;
+            game_restart(&mut game);
+            game
+        },
+    };
+    let mut session = session_init();
+    // Whether this deal has been offered autocomplete already, so it is only asked once
+    let mut offered = false;
+
+    let mut ch;
+    let mut src_pile;
+    let mut src_depth;
+    let mut dest_pile;
+    loop {
+        term::clear();
+        if game_won(&game) {
+            term::printw("=======================================\nWIN\n=======================================");
+            record_game(&mut session, &game_result(&game));
+            game_restart(&mut game);
+            offered = false;
+        }
+        print_game(&game, &session);
+        if game_status(&game) == GameStatus::Lost {
+            term::printw("\nNo moves can get this deal any further, r deals again\n");
+        }
+        if !offered && can_autocomplete(&game) {
+            offered = true;
+            term::printw("\nEvery card is face up, finish the game? (y/n): ");
+            term::refresh();
+            if char::from_u32(term::getch() as u32) == Some('y') {
+                autocomplete(&mut game);
+                continue;
+            }
+        }
+        term::refresh();
+        let mut valid = false;
+        while !valid {
+            valid = false;
+            loop {
+                term::printw("\nSrc pile?: ");
+                term::refresh();
+                ch = term::getch();
+                src_pile = src_index_from_char(ch);
+
+                if src_pile != 99 { break };
+            }
+
+            if src_pile == 12 {
+                draw(&mut game);
+                valid = true;
+            } else if src_pile == 13 {
+                record_game(&mut session, &game_result(&game));
+                game_restart(&mut game);
+                offered = false;
+                valid = true;
+            } else if src_pile == 14 {
+                valid = undo(&mut game).is_some();
+            } else if src_pile == 15 {
+                valid = redo(&mut game).is_some();
+            } else if src_pile == 16 {
+                match save_game(&game, &path) {
+                    Ok(()) => term::printw(&format!("\nSaved to {}\n", path.display())),
+                    Err(e) => term::printw(&format!("\nCould not save: {}\n", e)),
+                };
+            } else if src_pile == 18 {
+                game.auto_play = !game.auto_play;
+                term::printw(&format!("\nAuto play {}\n", if game.auto_play { "on" } else { "off" }));
+                if game.auto_play {
+                    valid = auto_play_safe(&mut game) > 0;
+                }
+            } else if src_pile == 17 {
+                if let Err(e) = save_game(&game, &path) {
+                    term::printw(&format!("\nCould not save: {}, quit anyway? (y/n): ", e));
+                    if char::from_u32(term::getch() as u32) != Some('y') {
+                        continue;
+                    }
+                }
+                term::endwin();
+                return;
+            } else {
+
+                loop {
+                    term::printw("\nsrc depth?: ");
+                    term::refresh();
+                    ch = term::getch();
+                    src_depth = depth_from_char(ch);
+                    if src_depth != 99 { break };
+                }
+
+                loop {
+                    term::printw("\ndest_pile?: ");
+                    term::refresh();
+                    ch = term::getch();
+                    dest_pile = dest_index_from_char(ch);
+                    if dest_pile != 99 { break };
+                }
+
+                term::printw(&format!("\n{}, {}, {}\n", src_pile, src_depth, dest_pile));
+                term::refresh();
+                match move_from_indices(&game, src_pile, src_depth, dest_pile)
+                        .and_then(|mv| make_move(&mut game, mv)) {
+                    Ok(_) => valid = true,
+                    Err(e) => { term::printw(&format!("Error: {}\n", e)); },
+                }
+            }
+        }
+    }
+}
+
+// Offers to carry on with the game saved at path, if there is one. None to deal a new game
+fn continue_last_game(path: &Path) -> Option<Game> {
+    if !path.exists() {
+        return None;
+    }
+    term::printw("Continue last game? (y/n): ");
+    term::refresh();
+    if char::from_u32(term::getch() as u32) != Some('y') {
+        return None;
+    }
+    match load_game(path) {
+        Ok(game) => Some(game),
+        Err(e) => {
+            term::printw(&format!("\nCould not load {}: {}\n", path.display(), e));
+            term::getch();
+            None
+        },
+    }
+}
+
+// Takes a character from getch() and returns the pile index
+// zero through 6 refer to the piles, h s d c refer to the foundation for that suit as indices
+// 7 through 10, q draws from the hand and w draws from the side deck to the hand
+// r restarts the game, u undoes the last move and y redoes it
+// v saves the game and x saves it and quits, a turns auto play to the foundations on or off
+fn src_index_from_char(ch: i32) -> usize {
+    match char::from_u32(ch as u32).expect("Invalid char") {
+        '1' => 0,
+        '2' => 1,
+        '3' => 2,
+        '4' => 3,
+        '5' => 4,
+        '6' => 5,
+        '7' => 6,
+        'h' => 7,
+        's' => 8,
+        'd' => 9,
+        'c' => 10,
+        'q' => 11,
+        'w' => 12,
+        'r' => 13,
+        'u' => 14,
+        'y' => 15,
+        'v' => 16,
+        'x' => 17,
+        'a' => 18,
+        _ => 99,
+    }
+}
+
+fn dest_index_from_char(ch: i32) -> usize {
+    match char::from_u32(ch as u32).expect("Invalid char") {
+        '1' => 0,
+        '2' => 1,
+        '3' => 2,
+        '4' => 3,
+        '5' => 4,
+        '6' => 5,
+        '7' => 6,
+        'h' => 7,
+        's' => 8,
+        'd' => 9,
+        'c' => 10,
+        _ => 99,
+    }
+}
+
+fn depth_from_char(ch: i32) -> usize {
+    match char::from_u32(ch as u32).expect("Invalid char") {
+        '1' => 1,
+        '2' => 2,
+        '3' => 3,
+        '4' => 4,
+        '5' => 5,
+        '6' => 6,
+        '7' => 7,
+        '8' => 8,
+        '9' => 9,
+        _ =>  99,
+    }
+}
+
+// Converts the pile indices chosen at the prompts into a move. 7 - 10 name the foundation for
+// Hearts, Spades, Diamonds and Clubs, wherever that suit has been started, and 11 is the hand
+fn move_from_indices(game: &Game, src_pile: usize, src_depth: usize, dest_pile: usize) -> Result<Move, MoveError> {
+    let found = |i: usize| game.foundation_for(Suit::ALL[i - 7]).ok_or(MoveError::NoSuchFoundation(i - 7));
+
+    if dest_pile > 6 && src_depth != 1 {
+        return Err(MoveError::TooManyToFoundation);
+    }
+    Ok(match (src_pile, dest_pile) {
+        (11, d) if d > 6 => Move::WasteToFoundation { dest: found(d)? },
+        (11, d) => {
+            if src_depth != 1 {
+                return Err(MoveError::TooManyFromHand);
+            }
+            Move::WasteToTableau { dest: d }
+        },
+        (s, d) if s > 6 => {
+            if src_depth != 1 {
+                return Err(MoveError::TooManyFromFoundation);
+            }
+            Move::FoundationToTableau { src: found(s)?, dest: d }
+        },
+        (s, d) if d > 6 => Move::TableauToFoundation { src: s, dest: found(d)? },
+        (s, d) => Move::TableauToTableau { src: s, depth: src_depth, dest: d },
+    })
+}
@@ -0,0 +1,213 @@
+//! Hashing positions, for solvers, cycle detection and transposition tables.
+//!
+//! position_hash is a Zobrist hash: every card contributes a key for where it lies and the keys
+//! are XORed together, so a move only has to take out the keys of the cards it shifts and put
+//! back their new ones. Game keeps it up to date in Game::hash as moves are made and undone. A
+//! card on the tableau is keyed by the card it lies on rather than by its pile, and a card on a
+//! foundation by itself alone, so the order of the piles and of the foundations makes no
+//! difference. Scores, move counts and the like are not part of a position.
+//!
+//! canonical_key gives the same position as exact bytes, for when a collision would matter.
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use game::{Game, Card, Move, Suit};
+use game::SplitMix64;
+
+// What a key is for, kept apart in the top bits of the value that is mixed into a key
+const TABLEAU: u64 = 1 << 32;
+const FOUNDATION: u64 = 2 << 32;
+const HAND: u64 = 3 << 32;
+const SIDE_DECK: u64 = 4 << 32;
+const PASSES: u64 = 5 << 32;
+
+// Stands in for the card beneath the first card of a pile
+const PILE_BASE: usize = 52;
+
+// Every key but those for the passes made, which has no upper bound, worked out on first use
+struct Keys {
+    // By card, the card beneath it or PILE_BASE, and whether it is up
+    tableau: Vec<u64>,
+    // By card
+    foundation: Vec<u64>,
+    // By card and position, as the hand and side deck never hold more than 52 cards
+    hand: Vec<u64>,
+    side_deck: Vec<u64>,
+}
+
+static KEYS: OnceLock<Keys> = OnceLock::new();
+
+fn keys() -> &'static Keys {
+    KEYS.get_or_init(|| {
+        let placed = |place: u64| (0..52 * 52)
+            .map(|i| key(place | ((i / 52) as u64) << 16 | (i % 52) as u64))
+            .collect();
+        Keys {
+            tableau: (0..52 * 53 * 2)
+                .map(|i| key(TABLEAU | ((i / 106) as u64) << 16 | (i % 106) as u64))
+                .collect(),
+            foundation: (0..52).map(|i| key(FOUNDATION | i as u64)).collect(),
+            hand: placed(HAND),
+            side_deck: placed(SIDE_DECK),
+        }
+    })
+}
+
+/// The Zobrist hash of the whole position, worked out from scratch
+pub fn position_hash(game: &Game) -> u64 {
+    let keys = keys();
+    let mut hash = passes_hash(game)
+        ^ stack_hash(&keys.hand, &game.hand, 0..game.hand.len())
+        ^ stack_hash(&keys.side_deck, &game.side_deck, 0..game.side_deck.len());
+    for pile in &game.piles {
+        hash ^= pile_hash(pile, 0..pile.len());
+    }
+    for found in &game.foundations {
+        hash ^= found.iter().fold(0, |hash, card| hash ^ keys.foundation[card.index()]);
+    }
+    hash
+}
+
+/// Sets the game's hash from scratch, for a game whose cards have been changed other than by
+/// make_move and undo
+pub fn rehash(game: &mut Game) {
+    game.hash = position_hash(game);
+}
+
+/// The keys of the cards the move shifts, as they lie before the move is made, or after if
+/// `made`. XORing the hash with both brings it up to date, whether the move is being made or
+/// undone. `drawn` is the number of cards turned over by a Draw and is ignored otherwise
+///
+/// Only the cards whose keys change are looked at: those moved, and the card left on top of the
+/// source pile, which may have been turned up. The rest of a run moved between piles keeps its
+/// keys, each card still lying on the same one
+pub fn move_hash(game: &Game, mv: Move, drawn: usize, made: bool) -> u64 {
+    let keys = keys();
+    let hand = &game.hand;
+    let side_deck = &game.side_deck;
+    let hand_top = || stack_hash(&keys.hand, hand, hand.len().saturating_sub(1)..hand.len());
+    let pile_top = |i: usize| top_of_pile(&game.piles[i], 1);
+    let found_top = |i: usize| game.foundations[i].last().map_or(0, |c| keys.foundation[c.index()]);
+    match (mv, made) {
+        (Move::Draw, false) => stack_hash(&keys.side_deck, side_deck, side_deck.len() - drawn..side_deck.len()),
+        (Move::Draw, true) => stack_hash(&keys.hand, hand, hand.len() - drawn..hand.len()),
+        (Move::Recycle, false) => stack_hash(&keys.hand, hand, 0..hand.len()) ^ passes_hash(game),
+        (Move::Recycle, true) => stack_hash(&keys.side_deck, side_deck, 0..side_deck.len()) ^ passes_hash(game),
+        (Move::WasteToTableau { .. }, false) | (Move::WasteToFoundation { .. }, false) => hand_top(),
+        (Move::WasteToTableau { dest }, true) => pile_top(dest),
+        (Move::WasteToFoundation { dest }, true) => found_top(dest),
+        (Move::TableauToTableau { src, depth, .. }, false) => {
+            let first = game.piles[src].len() - depth;
+            pile_hash(&game.piles[src], first.saturating_sub(1)..first + 1)
+        },
+        (Move::TableauToTableau { src, depth, dest }, true) => {
+            let first = game.piles[dest].len() - depth;
+            pile_top(src) ^ pile_hash(&game.piles[dest], first..first + 1)
+        },
+        (Move::TableauToFoundation { src, .. }, false) => top_of_pile(&game.piles[src], 2),
+        (Move::TableauToFoundation { src, dest }, true) => pile_top(src) ^ found_top(dest),
+        (Move::FoundationToTableau { src, .. }, false) => found_top(src),
+        (Move::FoundationToTableau { dest, .. }, true) => pile_top(dest),
+    }
+}
+
+fn key(feature: u64) -> u64 {
+    SplitMix64::new(feature).next_u64()
+}
+
+// The keys of the pile's cards in the range, each keyed by the card it lies on
+fn pile_hash(pile: &[Card], range: Range<usize>) -> u64 {
+    let keys = keys();
+    range.fold(0, |hash, i| {
+        let below = if i == 0 { PILE_BASE } else { pile[i - 1].index() };
+        hash ^ keys.tableau[(pile[i].index() * 53 + below) * 2 + pile[i].up as usize]
+    })
+}
+
+// The keys of the top n cards of the pile, or of all of it if it is shorter
+fn top_of_pile(pile: &[Card], n: usize) -> u64 {
+    pile_hash(pile, pile.len().saturating_sub(n)..pile.len())
+}
+
+// The hand and side deck are in a fixed place each, so their cards are keyed by position
+fn stack_hash(keys: &[u64], cards: &[Card], range: Range<usize>) -> u64 {
+    range.fold(0, |hash, i| hash ^ keys[cards[i].index() * 52 + i])
+}
+
+// Passes through the side deck only matter while they are limited
+fn passes_hash(game: &Game) -> u64 {
+    match game.rules.max_passes {
+        None => 0,
+        Some(_) => key(PASSES | game.recycles as u64),
+    }
+}
+
+/// The position as bytes, equal for two games exactly when their positions are, regardless of
+/// the order of the piles and of the foundations
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PositionKey(pub Vec<u8>);
+
+// Ends each pile, the hand and the side deck in a PositionKey. Cards are 0 - 51, plus 64 if up
+const END: u8 = 0xFF;
+
+/// The position as a PositionKey: the piles in sorted order, then how far each suit has got on
+/// the foundations, the hand, the side deck and the passes made if they are limited
+pub fn canonical_key(game: &Game) -> PositionKey {
+    let byte = |c: &Card| c.index() as u8 | if c.up { 64 } else { 0 };
+    let mut piles: Vec<Vec<u8>> = game.piles.iter().map(|p| p.iter().map(&byte).collect()).collect();
+    piles.sort();
+
+    let mut key = Vec::with_capacity(52 + 16);
+    for pile in piles {
+        key.extend(pile);
+        key.push(END);
+    }
+    for &suit in &Suit::ALL {
+        let found = game.foundations.iter().find(|f| f.first().map(|c| c.suit) == Some(suit));
+        key.push(found.map_or(0, |f| f.len() as u8));
+    }
+    key.extend(game.hand.iter().map(&byte));
+    key.push(END);
+    key.extend(game.side_deck.iter().map(&byte));
+    key.push(END);
+    if game.rules.max_passes.is_some() {
+        key.push(game.recycles as u8);
+    }
+    PositionKey(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::{Rules, game_init_with_rules, game_restart_with_seed, undo};
+    use player::{create_player, play_one_move};
+
+    #[test]
+    fn moves_keep_the_hash_as_if_from_scratch() {
+        for seed in 0..40 {
+            let rules = Rules { draw_count: 1 + seed as usize % 3, max_passes: Some(3), ..Rules::default() };
+            let mut game = game_init_with_rules(rules);
+            game_restart_with_seed(&mut game, seed);
+            let mut player = create_player(game);
+            let mut n = 0;
+            while n < 500 && play_one_move(&mut player) {
+                assert_eq!(player.game.hash, position_hash(&player.game));
+                n += 1;
+            }
+            while undo(&mut player.game).is_some() {
+                assert_eq!(player.game.hash, position_hash(&player.game));
+            }
+        }
+    }
+
+    #[test]
+    fn order_of_piles_and_foundations_is_ignored() {
+        let mut game = game_init_with_rules(Rules::default());
+        game_restart_with_seed(&mut game, 7);
+        let mut swapped = game.clone();
+        swapped.piles.reverse();
+        swapped.foundations.swap(0, 3);
+        assert_eq!(position_hash(&swapped), game.hash);
+        assert_eq!(canonical_key(&swapped), canonical_key(&game));
+    }
+}